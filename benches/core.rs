@@ -0,0 +1,187 @@
+use criterion::black_box;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+
+use ray_tracer_rust::dielectric::Dielectric;
+use ray_tracer_rust::filter::Filters;
+use ray_tracer_rust::hittable::HitRecord;
+use ray_tracer_rust::hittable::Hittable;
+use ray_tracer_rust::hittable::HittableList;
+use ray_tracer_rust::hittable::Hittables;
+use ray_tracer_rust::lambertian::Lambertian;
+use ray_tracer_rust::material::Material;
+use ray_tracer_rust::material::Materials;
+use ray_tracer_rust::metal::Metal;
+use ray_tracer_rust::ray::Ray;
+use ray_tracer_rust::render;
+use ray_tracer_rust::render::Framebuffer;
+use ray_tracer_rust::sampler::Samplers;
+use ray_tracer_rust::scene;
+use ray_tracer_rust::settings::Settings;
+use ray_tracer_rust::sphere::Sphere;
+use ray_tracer_rust::vec::Vec;
+
+fn v(x: f32, y: f32, z: f32) -> Vec {
+    Vec { x, y, z }
+}
+
+fn grey() -> Materials {
+    Materials::Lambertian(Lambertian {
+        albedo: v(0.5, 0.5, 0.5),
+    })
+}
+
+// A ray from the origin down -z, and one that misses everything.
+fn rays() -> (Ray, Ray) {
+    let hit = Ray {
+        orig: v(0.0, 0.0, 0.0),
+        dir: v(0.01, 0.02, -1.0),
+    };
+    let miss = Ray {
+        dir: v(0.0, 1.0, 0.0),
+        ..hit
+    };
+    (hit, miss)
+}
+
+// A 20 by 20 grid of small spheres in front of the camera.
+fn grid() -> HittableList {
+    let mut world: HittableList = Default::default();
+    for i in 0..20 {
+        for j in 0..20 {
+            world.objects.push(Hittables::Sphere(Sphere {
+                c: v(i as f32 - 9.5, j as f32 - 9.5, -5.0 - (i + j) as f32 * 0.1),
+                r: 0.4,
+                mat: grey(),
+            }));
+        }
+    }
+    world
+}
+
+fn sphere_hit(c: &mut Criterion) {
+    let sphere = Sphere {
+        c: v(0.0, 0.0, -5.0),
+        r: 1.0,
+        mat: grey(),
+    };
+    let (hit, miss) = rays();
+    let mut rec: HitRecord = Default::default();
+    c.bench_function("sphere hit", |b| {
+        b.iter(|| sphere.hit(black_box(&hit), 0.001, f32::MAX, &mut rec))
+    });
+    c.bench_function("sphere miss", |b| {
+        b.iter(|| sphere.hit(black_box(&miss), 0.001, f32::MAX, &mut rec))
+    });
+}
+
+fn list_hit(c: &mut Criterion) {
+    let linear = grid();
+    let mut bvh = grid();
+    bvh.build_bvh();
+    let (hit, _) = rays();
+    let mut rec: HitRecord = Default::default();
+    c.bench_function("list hit, 400 spheres", |b| {
+        b.iter(|| linear.hit(black_box(&hit), 0.001, f32::MAX, &mut rec))
+    });
+    c.bench_function("list hit, 400 spheres in a bvh", |b| {
+        b.iter(|| bvh.hit(black_box(&hit), 0.001, f32::MAX, &mut rec))
+    });
+    c.bench_function("bvh build, 400 spheres", |b| {
+        b.iter(|| black_box(grid()).build_bvh())
+    });
+}
+
+fn vec_math(c: &mut Criterion) {
+    let a = v(0.3, -0.5, 0.8);
+    let n = v(0.0, 1.0, 0.0);
+    c.bench_function("vec add", |b| b.iter(|| black_box(a).add(&[n])));
+    c.bench_function("vec dot", |b| b.iter(|| black_box(a).dot(&n)));
+    c.bench_function("vec cross", |b| b.iter(|| black_box(a).cross(&n)));
+    c.bench_function("vec unit", |b| b.iter(|| black_box(a).unit()));
+    c.bench_function("vec reflect", |b| b.iter(|| black_box(a).reflect(&n)));
+    c.bench_function("vec refract", |b| {
+        b.iter(|| black_box(a.unit()).refract(&n, 1.0 / 1.5))
+    });
+}
+
+fn scatter(c: &mut Criterion) {
+    let materials = [
+        ("lambertian", grey()),
+        (
+            "metal",
+            Materials::Metal(Metal {
+                albedo: v(0.8, 0.8, 0.8),
+                fuzz: 0.2,
+            }),
+        ),
+        ("dielectric", Materials::Dielectric(Dielectric { ir: 1.5 })),
+    ];
+    let (r, _) = rays();
+    let mut sampler = Samplers::new("independent", 1, 1).unwrap();
+    let rec = HitRecord {
+        p: v(0.0, 0.0, -4.0),
+        n: v(0.0, 0.0, 1.0),
+        t: 4.0,
+        front_face: true,
+        ..Default::default()
+    };
+    for (name, mat) in materials {
+        let mut att = Default::default();
+        let mut scattered = Default::default();
+        c.bench_function(&format!("{name} scatter"), |b| {
+            b.iter(|| mat.scatter(black_box(&r), &rec, &mut att, &mut scattered, &mut sampler))
+        });
+    }
+}
+
+fn small_render(c: &mut Criterion) {
+    let s = scene::parse(
+        "lookfrom 0 1 4
+        sphere -1 0.5 0 0.5 metal 0.8 0.8 0.8 0.2
+        sphere 1 0.5 0 0.5 dielectric 1.5
+        sphere 0 0.5 -1 0.5 lambertian 0.7 0.3 0.3
+        sphere 0 -100 0 100 lambertian 0.5 0.5 0.5",
+    )
+    .unwrap();
+    let mut world = s.world.clone();
+    world.build_bvh();
+    let settings = Settings {
+        image_width: 32,
+        aspect_ratio: 2.0,
+        n_samples: 4,
+        ..Default::default()
+    };
+    let cam = s.camera.build(settings.aspect_ratio, &world).unwrap().0;
+    let filter = Filters::new("box", None).unwrap();
+    c.bench_function("render 32x16 at 4 spp", |b| {
+        b.iter(|| {
+            let mut sampler = Samplers::new("sobol", 7, 4).unwrap();
+            let mut fb = Framebuffer::new(settings.image_width, settings.image_height());
+            render::render(
+                &settings,
+                &world,
+                &cam,
+                &mut sampler,
+                &filter,
+                &mut fb,
+                None,
+                |_| true,
+                |_| Ok(()),
+            )
+            .unwrap();
+            fb
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    sphere_hit,
+    list_hit,
+    vec_math,
+    scatter,
+    small_render
+);
+criterion_main!(benches);
@@ -0,0 +1,187 @@
+use crate::hittable::HittableList;
+use crate::scene::CameraDesc;
+use crate::vec::Vec;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    // Cubic Bezier segments with handles along the slope between the
+    // neighbouring keys, so motion passes smoothly through every key.
+    Bezier,
+}
+
+// Keyframed values of one property, sorted by frame. Before the first key
+// and after the last the property holds still.
+#[derive(Clone, Debug, Default)]
+pub struct Track<const N: usize> {
+    keys: std::vec::Vec<(f32, [f32; N])>,
+}
+
+impl<const N: usize> Track<N> {
+    // Adds a key, replacing any existing key on the same frame.
+    pub fn insert(&mut self, frame: f32, value: [f32; N]) {
+        let i = self.keys.partition_point(|k| k.0 < frame);
+        if self.keys.get(i).is_some_and(|k| k.0 == frame) {
+            self.keys[i].1 = value;
+        } else {
+            self.keys.insert(i, (frame, value));
+        }
+    }
+
+    fn range(&self) -> Option<(f32, f32)> {
+        Some((self.keys.first()?.0, self.keys.last()?.0))
+    }
+
+    // Rate of change at key i, from its neighbours.
+    fn slope(&self, i: usize) -> [f32; N] {
+        let a = i.saturating_sub(1);
+        let b = (i + 1).min(self.keys.len() - 1);
+        let (ta, pa) = self.keys[a];
+        let (tb, pb) = self.keys[b];
+        std::array::from_fn(|c| (pb[c] - pa[c]) / (tb - ta))
+    }
+
+    pub fn eval(&self, frame: f32, interpolation: Interpolation) -> Option<[f32; N]> {
+        let (first, last) = (self.keys.first()?, self.keys.last()?);
+        if frame <= first.0 {
+            return Some(first.1);
+        }
+        if frame >= last.0 {
+            return Some(last.1);
+        }
+        let i = self.keys.partition_point(|k| k.0 <= frame) - 1;
+        let (t0, p0) = self.keys[i];
+        let (t1, p1) = self.keys[i + 1];
+        let u = (frame - t0) / (t1 - t0);
+        Some(match interpolation {
+            Interpolation::Linear => std::array::from_fn(|c| p0[c] + (p1[c] - p0[c]) * u),
+            Interpolation::Bezier => {
+                let (m0, m1) = (self.slope(i), self.slope(i + 1));
+                let h = (t1 - t0) / 3.0;
+                let v = 1.0 - u;
+                std::array::from_fn(|c| {
+                    let c0 = p0[c] + m0[c] * h;
+                    let c1 = p1[c] - m1[c] * h;
+                    v * v * v * p0[c]
+                        + 3.0 * v * v * u * c0
+                        + 3.0 * v * u * u * c1
+                        + u * u * u * p1[c]
+                })
+            }
+        })
+    }
+}
+
+fn vec3([x, y, z]: [f32; 3]) -> Vec {
+    Vec { x, y, z }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Animation {
+    pub interpolation: Interpolation,
+    // Frames to render, inclusive. Defaults to the keyed frames.
+    pub frames: Option<(i32, i32)>,
+    pub lookfrom: Track<3>,
+    pub lookat: Track<3>,
+    pub vfov: Track<1>,
+    pub focus_dist: Track<1>,
+    // Offsets of objects, by their index in the scene, from where they
+    // were declared.
+    pub translate: std::vec::Vec<(usize, Track<3>)>,
+}
+
+impl Animation {
+    pub fn frame_range(&self) -> Option<(i32, i32)> {
+        if self.frames.is_some() {
+            return self.frames;
+        }
+        [
+            self.lookfrom.range(),
+            self.lookat.range(),
+            self.vfov.range(),
+            self.focus_dist.range(),
+        ]
+        .into_iter()
+        .chain(self.translate.iter().map(|(_, t)| t.range()))
+        .flatten()
+        .map(|(a, b)| (a.floor() as i32, b.ceil() as i32))
+        .reduce(|(a0, b0), (a1, b1)| (a0.min(a1), b0.max(b1)))
+    }
+
+    // How far each of n objects moves from frame to the next.
+    pub fn motion(&self, frame: f32, n: usize) -> std::vec::Vec<Vec> {
+        let mut offsets = vec![Vec::default(); n];
+        for (i, track) in &self.translate {
+            let a = track.eval(frame, self.interpolation);
+            let b = track.eval(frame + 1.0, self.interpolation);
+            if let (Some(o), Some(a), Some(b)) = (offsets.get_mut(*i), a, b) {
+                *o = vec3(b).sub(&[vec3(a)]);
+            }
+        }
+        offsets
+    }
+
+    // Poses the camera and objects for the given frame.
+    pub fn apply(&self, frame: f32, cam: &mut CameraDesc, world: &mut HittableList) {
+        let interp = self.interpolation;
+        if let Some(v) = self.lookfrom.eval(frame, interp) {
+            cam.lf = vec3(v);
+        }
+        if let Some(v) = self.lookat.eval(frame, interp) {
+            cam.la = vec3(v);
+        }
+        if let Some([v]) = self.vfov.eval(frame, interp) {
+            cam.vfov = v;
+        }
+        if let Some([v]) = self.focus_dist.eval(frame, interp) {
+            cam.fd = v;
+        }
+        for (i, track) in &self.translate {
+            if let (Some(obj), Some(v)) = (world.objects.get_mut(*i), track.eval(frame, interp)) {
+                obj.translate(&vec3(v));
+            }
+        }
+    }
+}
+
+// Path for one frame of a sequence, e.g. img.ppm becomes img_0001.ppm.
+pub fn frame_path(path: &str, frame: i32) -> String {
+    let name = path.rfind('/').map_or(0, |i| i + 1);
+    match path[name..].rfind('.') {
+        Some(dot) => {
+            let (stem, ext) = path.split_at(name + dot);
+            format!("{stem}_{frame:04}{ext}")
+        }
+        None => format!("{path}_{frame:04}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolation() {
+        let mut t: Track<1> = Default::default();
+        t.insert(10.0, [4.0]);
+        t.insert(0.0, [0.0]);
+        t.insert(20.0, [8.0]);
+        assert_eq!(t.eval(-5.0, Interpolation::Linear), Some([0.0]));
+        assert_eq!(t.eval(5.0, Interpolation::Linear), Some([2.0]));
+        assert_eq!(t.eval(25.0, Interpolation::Bezier), Some([8.0]));
+        // Evenly spaced keys on a line stay on it with Bezier handles.
+        let [v] = t.eval(15.0, Interpolation::Bezier).unwrap();
+        assert!((v - 6.0).abs() < 1e-5, "{v}");
+
+        t.insert(10.0, [10.0]);
+        let [v] = t.eval(10.0, Interpolation::Bezier).unwrap();
+        assert_eq!(v, 10.0);
+        // An overshooting middle key is approached smoothly from below.
+        let [v] = t.eval(9.0, Interpolation::Bezier).unwrap();
+        assert!(v > 9.0 && v < 10.0, "{v}");
+
+        assert_eq!(frame_path("out/img.ppm", 7), "out/img_0007.ppm");
+        assert_eq!(frame_path("v1.0/img", 12), "v1.0/img_0012");
+    }
+}
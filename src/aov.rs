@@ -0,0 +1,404 @@
+use crate::camera::Camera;
+use crate::filter::Filters;
+use crate::hittable::HitRecord;
+use crate::hittable::Hittable;
+use crate::hittable::HittableList;
+use crate::material::Lobe;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::render::Framebuffer;
+use crate::render::Region;
+use crate::vec::Colour;
+use crate::vec::Vec;
+
+// Arbitrary output variables: data about the first surface seen through
+// each pixel, and the light reaching the camera split by the paths it
+// took, for compositing.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Aov {
+    // Distance from the camera, infinite where nothing is hit.
+    Depth,
+    // World space surface normal, facing the camera.
+    Normal,
+    Albedo,
+    // World space position.
+    Position,
+    // Index of the object in the scene, -1 where nothing is hit.
+    ObjectId,
+    // Index of the material among the scene's distinct materials.
+    MaterialId,
+    // Film motion, in pixels, of the surface to where it is seen in the
+    // next frame.
+    Motion,
+    // Light passes, named by the light path expressions they match: C is
+    // the camera, L the sky, D, S and T diffuse, specular and transmission
+    // bounces. Every path falls in exactly one of them, by its first
+    // bounce, so together they add up to the beauty image.
+    //
+    // CL: the sky seen directly.
+    Emission,
+    // CDL
+    DiffuseDirect,
+    // CD.+L
+    DiffuseIndirect,
+    // CS.*L
+    Specular,
+    // CT.*L
+    Transmission,
+}
+
+pub const ALL: [Aov; 12] = [
+    Aov::Depth,
+    Aov::Normal,
+    Aov::Albedo,
+    Aov::Position,
+    Aov::ObjectId,
+    Aov::MaterialId,
+    Aov::Motion,
+    Aov::Emission,
+    Aov::DiffuseDirect,
+    Aov::DiffuseIndirect,
+    Aov::Specular,
+    Aov::Transmission,
+];
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::ObjectId => "object",
+            Aov::MaterialId => "material",
+            Aov::Motion => "motion",
+            Aov::Emission => "emission",
+            Aov::DiffuseDirect => "diffuse_direct",
+            Aov::DiffuseIndirect => "diffuse_indirect",
+            Aov::Specular => "specular",
+            Aov::Transmission => "transmission",
+        }
+    }
+
+    // Light passes are filled from the beauty samples, filtered the same
+    // way, rather than from the first hit.
+    fn is_light(&self) -> bool {
+        matches!(
+            self,
+            Aov::Emission
+                | Aov::DiffuseDirect
+                | Aov::DiffuseIndirect
+                | Aov::Specular
+                | Aov::Transmission
+        )
+    }
+
+    // Data that doesn't blend, like depth and IDs, comes from the first
+    // sample of each pixel rather than being averaged.
+    fn first_sample_only(&self) -> bool {
+        matches!(
+            self,
+            Aov::Depth | Aov::Position | Aov::ObjectId | Aov::MaterialId
+        )
+    }
+
+    fn channels(&self) -> usize {
+        match self {
+            Aov::Depth | Aov::ObjectId | Aov::MaterialId => 1,
+            _ => 3,
+        }
+    }
+}
+
+// The light pass of a path that bounced the given number of times, the
+// first of them off the given lobe.
+pub fn light_pass(first: Option<Lobe>, bounces: i32) -> Aov {
+    match first {
+        None => Aov::Emission,
+        Some(Lobe::Diffuse) if bounces == 1 => Aov::DiffuseDirect,
+        Some(Lobe::Diffuse) => Aov::DiffuseIndirect,
+        Some(Lobe::Specular) => Aov::Specular,
+        Some(Lobe::Transmission) => Aov::Transmission,
+    }
+}
+
+// Parses a comma separated list of AOV names, or "all".
+pub fn parse_list(names: &str) -> Result<std::vec::Vec<Aov>, String> {
+    if names == "all" {
+        return Ok(ALL.to_vec());
+    }
+    names
+        .split(',')
+        .map(|n| {
+            ALL.iter()
+                .find(|a| a.name() == n)
+                .copied()
+                .ok_or(format!("unknown AOV: {n}"))
+        })
+        .collect()
+}
+
+// Where the next frame sees things, for motion vectors.
+pub struct NextFrame {
+    pub cam: Camera,
+    // Movement of each object from this frame to the next.
+    pub offsets: std::vec::Vec<Vec>,
+}
+
+pub struct Aovs {
+    pub layers: std::vec::Vec<(Aov, Framebuffer)>,
+    // Layers written out; the rest are only kept for denoising.
+    pub save: std::vec::Vec<Aov>,
+    // Material ID of each object.
+    material_ids: std::vec::Vec<f32>,
+    pub next: Option<NextFrame>,
+}
+
+impl Aovs {
+    pub fn new(aovs: &[Aov], world: &HittableList, width: i32, height: i32) -> Aovs {
+        let mut materials = std::vec::Vec::new();
+        let material_ids = world
+            .objects
+            .iter()
+            .map(|obj| {
+                let mat = obj.material();
+                match materials.iter().position(|m| *m == mat) {
+                    Some(i) => i as f32,
+                    None => {
+                        materials.push(mat);
+                        (materials.len() - 1) as f32
+                    }
+                }
+            })
+            .collect();
+        Aovs {
+            layers: aovs
+                .iter()
+                .map(|a| (*a, Framebuffer::new(width, height)))
+                .collect(),
+            save: aovs.to_vec(),
+            material_ids,
+            next: None,
+        }
+    }
+
+    // Records hit, what camera ray r for pixel (i, j) sees first, or None
+    // for a miss. film is the film position r was generated for.
+    pub fn add_sample(
+        &mut self,
+        i: i32,
+        j: i32,
+        film: (f32, f32),
+        r: &Ray,
+        hit: Option<&HitRecord>,
+        cam: &Camera,
+    ) {
+        for (aov, fb) in &mut self.layers {
+            if aov.is_light() {
+                continue;
+            }
+            let px = &mut fb.pixels[(j * fb.width + i) as usize];
+            if aov.first_sample_only() && px.weight > 0.0 {
+                continue;
+            }
+            let scalar = |v: f32| Vec { x: v, y: v, z: v };
+            let v = match (aov, hit) {
+                (Aov::Depth, Some(rec)) => scalar(rec.t * r.dir.len()),
+                (Aov::Depth, None) => scalar(f32::INFINITY),
+                (Aov::Normal, Some(rec)) => rec.n,
+                (Aov::Albedo, Some(rec)) => rec.mat.albedo(),
+                (Aov::Position, Some(rec)) => rec.p,
+                (Aov::ObjectId, Some(rec)) => scalar(rec.object as f32),
+                (Aov::MaterialId, Some(rec)) => scalar(self.material_ids[rec.object]),
+                (Aov::ObjectId | Aov::MaterialId, None) => scalar(-1.0),
+                (Aov::Motion, Some(rec)) => match &self.next {
+                    Some(next) => {
+                        let p1 = rec.p.add(&[next.offsets[rec.object]]);
+                        let (s, t) = film;
+                        match (cam.project(&rec.p, s, t), next.cam.project(&p1, s, t)) {
+                            (Some((s0, t0)), Some((s1, t1))) => Vec {
+                                x: (s1 - s0) * (fb.width - 1) as f32,
+                                y: (t1 - t0) * (fb.height - 1) as f32,
+                                z: 0.0,
+                            },
+                            _ => Default::default(),
+                        }
+                    }
+                    None => Default::default(),
+                },
+                _ => Default::default(),
+            };
+            px.sum = px.sum.add(&[v]);
+            px.weight += 1.0;
+        }
+    }
+
+    // Adds a beauty sample, taken at film position (x, y), to its light
+    // pass, and black to the others so that every pass is normalised by
+    // the same filter weights as the beauty. The pixel the sample lies in
+    // counts it too, so passes fall back to the box average wherever the
+    // beauty does.
+    pub fn splat(&mut self, x: f32, y: f32, c: &Colour, pass: Aov, filter: &Filters) {
+        for (aov, fb) in self.layers.iter_mut().filter(|(a, _)| a.is_light()) {
+            let c = if *aov == pass { *c } else { Default::default() };
+            fb.splat(x, y, &c, filter);
+            let i = (x as i32).clamp(0, fb.width - 1);
+            let j = (y as i32).clamp(0, fb.height - 1);
+            fb.pixels[(j * fb.width + i) as usize].add_sample(&c);
+        }
+    }
+
+    pub fn layer(&self, aov: Aov) -> Option<&Framebuffer> {
+        self.layers
+            .iter()
+            .find(|(a, _)| *a == aov)
+            .map(|(_, fb)| fb)
+    }
+
+    // Writes each saved layer to a PFM file named after the beauty image, so
+    // img.ppm gets img.depth.pfm and so on, cut down to the region like
+    // the beauty image.
+    pub fn write(&self, output: &str, region: Option<&Region>, crop: bool) -> std::io::Result<()> {
+        for (aov, fb) in self.layers.iter().filter(|(a, _)| self.save.contains(a)) {
+            let path = path(output, aov.name());
+            match region {
+                Some(r) => fb.region(r, !crop).write_pfm(&path, aov.channels())?,
+                None => fb.write_pfm(&path, aov.channels())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn path(output: &str, name: &str) -> String {
+    let file = output.rfind('/').map_or(0, |i| i + 1);
+    let stem = match output[file..].rfind('.') {
+        Some(dot) => &output[..file + dot],
+        None => output,
+    };
+    format!("{stem}.{name}.pfm")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera;
+    use crate::hittable::HittableList;
+    use crate::hittable::Hittables;
+    use crate::lambertian::Lambertian;
+    use crate::material::Materials;
+    use crate::ray::Ray;
+    use crate::render;
+    use crate::sampler::Samplers;
+    use crate::sphere::Sphere;
+    use crate::vec::Vec;
+
+    #[test]
+    fn first_hit() {
+        assert_eq!(
+            parse_list("depth,motion").unwrap(),
+            [Aov::Depth, Aov::Motion]
+        );
+        assert!(parse_list("depth,colour").is_err());
+        assert_eq!(path("out/a.b/img.ppm", "depth"), "out/a.b/img.depth.pfm");
+
+        let red = Materials::Lambertian(Lambertian {
+            albedo: Vec {
+                x: 1.0,
+                ..Default::default()
+            },
+        });
+        let sphere = |z: f32| {
+            Hittables::Sphere(Sphere {
+                c: Vec {
+                    z,
+                    ..Default::default()
+                },
+                r: 1.0,
+                mat: red.clone(),
+            })
+        };
+        let mut world = HittableList::default();
+        world.objects = vec![sphere(-5.0), sphere(-100.0)];
+        let mut aovs = Aovs::new(&ALL, &world, 2, 1);
+        let cam = camera::init(
+            &Default::default(),
+            &Vec {
+                z: -1.0,
+                ..Default::default()
+            },
+            &Vec {
+                y: 1.0,
+                ..Default::default()
+            },
+            90.0,
+            2.0,
+            0.0,
+            1.0,
+        );
+        let next = camera::init(
+            &Vec {
+                x: -1.0,
+                ..Default::default()
+            },
+            &Vec {
+                x: -1.0,
+                z: -1.0,
+                ..Default::default()
+            },
+            &Vec {
+                y: 1.0,
+                ..Default::default()
+            },
+            90.0,
+            2.0,
+            0.0,
+            1.0,
+        );
+        aovs.next = Some(NextFrame {
+            cam: next,
+            offsets: vec![Default::default(); 2],
+        });
+
+        let r = Ray {
+            orig: Default::default(),
+            dir: Vec {
+                z: -2.0,
+                ..Default::default()
+            },
+        };
+        // The path tracer hands back what the camera ray hit first.
+        let mut sampler = Samplers::new("independent", 1, 1).unwrap();
+        let hit = |r: &Ray, sampler: &mut Samplers| render::trace(r, &world, 4, 4, sampler).3;
+        let first = hit(&r, &mut sampler);
+        assert_eq!(first.as_ref().map(|rec| rec.t), Some(2.0));
+        aovs.add_sample(0, 0, (0.5, 0.5), &r, first.as_ref(), &cam);
+        let miss = Ray {
+            dir: Vec {
+                y: 1.0,
+                ..Default::default()
+            },
+            ..r
+        };
+        let first = hit(&miss, &mut sampler);
+        assert!(first.is_none());
+        aovs.add_sample(1, 0, (0.5, 0.5), &miss, first.as_ref(), &cam);
+
+        let get = |aov: Aov, i: i32| {
+            let (_, fb) = aovs.layers.iter().find(|(a, _)| *a == aov).unwrap();
+            fb.at(i, 0).colour()
+        };
+        assert_eq!(get(Aov::Depth, 0).x, 4.0);
+        assert_eq!(get(Aov::Depth, 1).x, f32::INFINITY);
+        assert_eq!(get(Aov::Normal, 0).z, 1.0);
+        assert_eq!(get(Aov::Albedo, 0).x, 1.0);
+        assert_eq!(get(Aov::Position, 0).z, -4.0);
+        assert_eq!(get(Aov::ObjectId, 0).x, 0.0);
+        assert_eq!(get(Aov::ObjectId, 1).x, -1.0);
+        assert_eq!(get(Aov::MaterialId, 0).x, 0.0);
+        // Moving the camera left one unit moves a point 4 units away right by
+        // 1/16 of the image width.
+        let m = get(Aov::Motion, 0);
+        assert!((m.x - 1.0 / 16.0).abs() < 1e-5 && m.y.abs() < 1e-5, "{m:?}");
+    }
+}
@@ -0,0 +1,286 @@
+use crate::hittable::HitRecord;
+use crate::hittable::Hittable;
+use crate::hittable::Hittables;
+use crate::ray::Ray;
+use crate::vec::Point;
+
+// Axis aligned bounding box.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point {
+                x: self.min.x.min(other.min.x),
+                y: self.min.y.min(other.min.y),
+                z: self.min.z.min(other.min.z),
+            },
+            max: Point {
+                x: self.max.x.max(other.max.x),
+                y: self.max.y.max(other.max.y),
+                z: self.max.z.max(other.max.z),
+            },
+        }
+    }
+
+    fn centre(&self) -> Point {
+        self.min.add(&[self.max]).mulf(0.5)
+    }
+
+    // Slab test: whether r passes through the box between t_min and t_max.
+    pub fn hit(&self, r: &Ray, mut t_min: f32, mut t_max: f32) -> bool {
+        for (o, d, lo, hi) in [
+            (r.orig.x, r.dir.x, self.min.x, self.max.x),
+            (r.orig.y, r.dir.y, self.min.y, self.max.y),
+            (r.orig.z, r.dir.z, self.min.z, self.max.z),
+        ] {
+            let inv = 1.0 / d;
+            let (t0, t1) = ((lo - o) * inv, (hi - o) * inv);
+            let (t0, t1) = if inv < 0.0 { (t1, t0) } else { (t0, t1) };
+            // max and min skip the NaN of a ray lying in a slab's plane.
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Leaves hold up to this many objects.
+const LEAF_SIZE: usize = 2;
+
+#[derive(Copy, Clone, Debug)]
+struct Node {
+    bounds: Aabb,
+    // For a leaf, the first of its count objects in the BVH's order; for
+    // an interior node, whose left child follows it, the right child.
+    start: usize,
+    count: usize,
+}
+
+// Bounding volume hierarchy over a list of objects, split at the median
+// along the longest axis of the object centres.
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    nodes: std::vec::Vec<Node>,
+    order: std::vec::Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(objects: &[Hittables]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: std::vec::Vec::with_capacity(2 * objects.len()),
+            order: (0..objects.len()).collect(),
+        };
+        let bounds: std::vec::Vec<Aabb> = objects.iter().map(|o| o.bounding_box()).collect();
+        if !objects.is_empty() {
+            bvh.build(&bounds, 0, objects.len());
+        }
+        bvh
+    }
+
+    fn build(&mut self, bounds: &[Aabb], start: usize, end: usize) {
+        let items = &mut self.order[start..end];
+        let node_bounds = items
+            .iter()
+            .map(|&i| bounds[i])
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: node_bounds,
+            start,
+            count: end - start,
+        });
+        if end - start <= LEAF_SIZE {
+            return;
+        }
+
+        let centres = items
+            .iter()
+            .map(|&i| {
+                let c = bounds[i].centre();
+                Aabb { min: c, max: c }
+            })
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        let extent = centres.max.sub(&[centres.min]);
+        let axis = |p: Point| {
+            if extent.x >= extent.y && extent.x >= extent.z {
+                p.x
+            } else if extent.y >= extent.z {
+                p.y
+            } else {
+                p.z
+            }
+        };
+        let mid = items.len() / 2;
+        items.select_nth_unstable_by(mid, |&a, &b| {
+            axis(bounds[a].centre()).total_cmp(&axis(bounds[b].centre()))
+        });
+
+        self.build(bounds, start, start + mid);
+        self.nodes[node].start = self.nodes.len();
+        self.nodes[node].count = 0;
+        self.build(bounds, start + mid, end);
+    }
+
+    // Number of objects the tree was built over.
+    pub fn objects(&self) -> usize {
+        self.order.len()
+    }
+
+    // Finds the closest hit among objects, which must be those the BVH
+    // was built over, counting the boxes and objects tested in cost.
+    pub fn hit(
+        &self,
+        objects: &[Hittables],
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        rec: &mut HitRecord,
+        cost: &mut u32,
+    ) -> bool {
+        let mut temp_rec: HitRecord = Default::default();
+        let mut hit = false;
+        let mut closest = t_max;
+        if self.nodes.is_empty() {
+            return false;
+        }
+        // Median splits keep the tree balanced, so its depth, and the
+        // stack, stay well within 64 for any list that fits in memory.
+        let mut stack = [0; 64];
+        let mut top = 1;
+        while top > 0 {
+            top -= 1;
+            let n = stack[top];
+            let node = &self.nodes[n];
+            *cost += 1;
+            if !node.bounds.hit(r, t_min, closest) {
+                continue;
+            }
+            if node.count == 0 {
+                stack[top] = node.start;
+                stack[top + 1] = n + 1;
+                top += 2;
+                continue;
+            }
+            for &i in &self.order[node.start..node.start + node.count] {
+                *cost += 1;
+                if objects[i].hit(r, t_min, closest, &mut temp_rec) {
+                    hit = true;
+                    closest = temp_rec.t;
+                    *rec = temp_rec.clone();
+                    rec.object = i;
+                }
+            }
+        }
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hittable::HitRecord;
+    use crate::hittable::Hittable;
+    use crate::hittable::HittableList;
+    use crate::hittable::Hittables;
+    use crate::ray::Ray;
+    use crate::sphere::Sphere;
+    use crate::util::Rng;
+    use crate::vec::Vec;
+
+    #[test]
+    fn matches_linear_search() {
+        let mut rng = Rng::new(3);
+        let mut rand = |s: f32| (rng.next_f32() - 0.5) * s;
+        let mut world = HittableList::default();
+        for _ in 0..200 {
+            world.objects.push(Hittables::Sphere(Sphere {
+                c: Vec {
+                    x: rand(20.0),
+                    y: rand(20.0),
+                    z: rand(20.0),
+                },
+                r: 1.0 + rand(1.0),
+                mat: Default::default(),
+            }));
+        }
+        let linear = world.clone();
+        world.build_bvh();
+
+        let mut hits = 0;
+        let mut total_cost = 0;
+        for _ in 0..2000 {
+            let r = Ray {
+                orig: Vec {
+                    x: rand(20.0),
+                    y: rand(20.0),
+                    z: rand(20.0),
+                },
+                dir: Vec {
+                    x: rand(1.0),
+                    y: rand(1.0),
+                    z: rand(1.0),
+                },
+            };
+            let (mut a, mut b): (HitRecord, HitRecord) = Default::default();
+            let mut cost = 0;
+            let hit = world.hit_cost(&r, 0.001, f32::MAX, &mut a, &mut cost);
+            assert_eq!(hit, linear.hit(&r, 0.001, f32::MAX, &mut b));
+            if hit {
+                hits += 1;
+                assert_eq!((a.object, a.t), (b.object, b.t));
+            }
+            total_cost += cost;
+        }
+        assert!(hits > 50, "{hits}");
+        // Far fewer tests than checking every object.
+        assert!(total_cost < 2000 * 200 / 4, "{total_cost}");
+    }
+
+    #[test]
+    fn ignores_a_stale_tree() {
+        let sphere = |x: f32| {
+            Hittables::Sphere(Sphere {
+                c: Vec {
+                    x,
+                    z: -5.0,
+                    ..Default::default()
+                },
+                r: 1.0,
+                mat: Default::default(),
+            })
+        };
+        let at = |x: f32| Ray {
+            orig: Vec {
+                x,
+                ..Default::default()
+            },
+            dir: Vec {
+                z: -1.0,
+                ..Default::default()
+            },
+        };
+        let mut world = HittableList::default();
+        world.objects.push(sphere(0.0));
+        world.objects.push(sphere(3.0));
+        world.build_bvh();
+
+        // Objects added or removed after building are still found, or no
+        // longer looked up.
+        world.objects.push(sphere(6.0));
+        let mut rec: HitRecord = Default::default();
+        assert!(world.hit(&at(6.0), 0.001, f32::MAX, &mut rec));
+        assert_eq!(rec.object, 2);
+        world.objects.truncate(1);
+        assert!(!world.hit(&at(3.0), 0.001, f32::MAX, &mut rec));
+        assert!(world.hit(&at(0.0), 0.001, f32::MAX, &mut rec));
+    }
+}
@@ -1,66 +1,794 @@
-pub mod camera {
-    use crate::ray;
-    use crate::vec;
-
-    #[derive(Default)]
-    pub struct Camera {
-        pub orig: vec::vec::Point,
-        pub llc: vec::vec::Point,
-        pub hori: vec::vec::Vec,
-        pub vert: vec::vec::Vec,
-        pub w: vec::vec::Vec,
-        pub u: vec::vec::Vec,
-        pub v: vec::vec::Vec,
-        pub lr: f32,
-    }
-
-    pub fn init(
-        lf: &vec::vec::Point,
-        la: &vec::vec::Point,
-        vup: &vec::vec::Vec,
-        vfov: f32,
-        ar: f32,
-        ap: f32,
-        fd: f32,
-    ) -> Camera {
-        let theta = std::f32::consts::PI * vfov / 180.0;
-        let h = (theta / 2.0).tan();
-        let vh = 2.0 * h;
-        let vw = ar * vh;
-
-        let w = lf.sub(&[*la]).unit();
-        let u = vup.cross(&w).unit();
-        let v = w.cross(&u);
-
-        let hori = u.mulf(vw * fd);
-        let vert = v.mulf(vh * fd);
-        let llc = lf.sub(&[hori.divf(2.0), vert.divf(2.0), w.mulf(fd)]);
-        let lr = ap / 2.0;
-
-        Camera {
-            orig: *lf,
-            llc,
-            hori,
-            vert,
-            w,
-            u,
-            v,
-            lr,
+use crate::hittable;
+use crate::hittable::Hittable;
+use crate::lens;
+use crate::ray;
+use crate::sampler;
+use crate::vec;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    // Image radius proportional to the angle from the view axis.
+    Equidistant,
+    // Image radius proportional to sin(angle / 2), preserving solid angle.
+    Equisolid,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    Orthographic,
+    // fov is the full angle, in radians, covered by the image circle, which
+    // spans the image height.
+    Fisheye {
+        fov: f32,
+        mapping: FisheyeMapping,
+    },
+    // Full 360 x 180 degree panorama centred on the view direction.
+    Equirectangular,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum StereoLayout {
+    #[default]
+    Mono,
+    // Left eye on the left half of the image.
+    SideBySide,
+    // Left eye on the top half of the image.
+    TopBottom,
+}
+
+// Both eyes share the camera basis and are offset by +-iod / 2 along u. The
+// eyes are converged so that objects at the convergence distance have zero
+// parallax; a convergence of 0 keeps the eyes parallel. Equirectangular
+// cameras render omnidirectional stereo, offsetting each ray's origin
+// tangentially to the viewing circle.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Stereo {
+    pub layout: StereoLayout,
+    pub iod: f32,
+    pub convergence: f32,
+}
+
+impl Stereo {
+    // Size of the full image holding both eyes, given the size of one eye.
+    pub fn film_size(&self, width: i32, height: i32) -> (i32, i32) {
+        match self.layout {
+            StereoLayout::Mono => (width, height),
+            StereoLayout::SideBySide => (2 * width, height),
+            StereoLayout::TopBottom => (width, 2 * height),
+        }
+    }
+
+    // Splits full image coordinates into the eye (-1 left, 0 mono, 1 right)
+    // and the coordinates within that eye's view.
+    fn eye(&self, s: f32, t: f32) -> (f32, f32, f32) {
+        match self.layout {
+            StereoLayout::Mono => (0.0, s, t),
+            StereoLayout::SideBySide if s < 0.5 => (-1.0, 2.0 * s, t),
+            StereoLayout::SideBySide => (1.0, 2.0 * s - 1.0, t),
+            StereoLayout::TopBottom if t >= 0.5 => (-1.0, s, 2.0 * t - 1.0),
+            StereoLayout::TopBottom => (1.0, s, 2.0 * t),
+        }
+    }
+
+    // Full image coordinates of (s, t) in the given eye's view, the inverse
+    // of eye.
+    fn film(&self, eye: f32, s: f32, t: f32) -> (f32, f32) {
+        match self.layout {
+            StereoLayout::Mono => (s, t),
+            StereoLayout::SideBySide if eye < 0.0 => (s / 2.0, t),
+            StereoLayout::SideBySide => ((s + 1.0) / 2.0, t),
+            StereoLayout::TopBottom if eye < 0.0 => (s, (t + 1.0) / 2.0),
+            StereoLayout::TopBottom => (s, t / 2.0),
+        }
+    }
+}
+
+// Lens aperture shape used for defocus blur, scaled to the lens radius.
+#[derive(Clone, Debug, Default)]
+pub enum Aperture {
+    #[default]
+    Circle,
+    // Regular polygon with the given number of blades inscribed in the
+    // lens circle, rotated by rotation radians.
+    Polygon {
+        blades: u32,
+        rotation: f32,
+    },
+    Mask(ApertureMask),
+}
+
+// Aperture given by a greyscale image, sampled in proportion to pixel
+// brightness. The longer side of the image spans the lens diameter.
+#[derive(Clone, Debug)]
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    // Cumulative distributions: row_cdf over rows, and one cdf over the
+    // columns of each row, all normalised to end at 1.
+    row_cdf: std::vec::Vec<f32>,
+    col_cdf: std::vec::Vec<f32>,
+}
+
+// Finds the bucket of a normalised cdf containing u, and where in the
+// bucket u falls.
+fn sample_cdf(cdf: &[f32], u: f32) -> (usize, f32) {
+    let i = cdf.partition_point(|&c| c <= u).min(cdf.len() - 1);
+    let lo = if i == 0 { 0.0 } else { cdf[i - 1] };
+    let frac = if cdf[i] > lo {
+        (u - lo) / (cdf[i] - lo)
+    } else {
+        0.5
+    };
+    (i, frac)
+}
+
+impl ApertureMask {
+    // pixels holds width * height brightness values, top row first.
+    pub fn new(width: usize, height: usize, pixels: &[f32]) -> Option<ApertureMask> {
+        if width == 0 || height == 0 || Some(pixels.len()) != width.checked_mul(height) {
+            return None;
+        }
+        let mut row_cdf = std::vec::Vec::with_capacity(height);
+        let mut col_cdf = std::vec::Vec::with_capacity(width * height);
+        let mut total = 0.0;
+        for row in pixels.chunks_exact(width).take(height) {
+            let mut acc = 0.0;
+            for p in row {
+                acc += p.max(0.0);
+                col_cdf.push(acc);
+            }
+            if acc > 0.0 {
+                let n = col_cdf.len();
+                for c in &mut col_cdf[n - width..] {
+                    *c /= acc;
+                }
+            }
+            total += acc;
+            row_cdf.push(total);
+        }
+        if row_cdf.len() != height || total <= 0.0 {
+            return None;
+        }
+        for r in &mut row_cdf {
+            *r /= total;
+        }
+        Some(ApertureMask {
+            width,
+            height,
+            row_cdf,
+            col_cdf,
+        })
+    }
+
+    fn sample(&self, u1: f32, u2: f32) -> (f32, f32) {
+        let (row, fy) = sample_cdf(&self.row_cdf, u2);
+        let cols = &self.col_cdf[row * self.width..(row + 1) * self.width];
+        let (col, fx) = sample_cdf(cols, u1);
+        let scale = 2.0 / self.width.max(self.height) as f32;
+        let x = (col as f32 + fx - self.width as f32 / 2.0) * scale;
+        let y = (self.height as f32 / 2.0 - (row as f32 + fy)) * scale;
+        (x, y)
+    }
+}
+
+impl Aperture {
+    // Point on the aperture in lens coordinates, within the unit disk for
+    // the built in shapes.
+    pub fn sample(&self, sampler: &mut dyn sampler::Sampler) -> vec::Vec {
+        match self {
+            Aperture::Circle => vec::rand_unit_disk(sampler),
+            Aperture::Polygon { blades, rotation } => {
+                let (u1, u2) = sampler.get_2d();
+                let n = (*blades).max(3) as f32;
+                // Pick one of the triangles fanning out from the centre,
+                // then a uniform point inside it.
+                let k = (u1 * n).floor().min(n - 1.0);
+                let u1 = u1 * n - k;
+                let step = 2.0 * std::f32::consts::PI / n;
+                let a0 = rotation + k * step;
+                let a1 = a0 + step;
+                let r = u1.sqrt();
+                let x = r * ((1.0 - u2) * a0.cos() + u2 * a1.cos());
+                let y = r * ((1.0 - u2) * a0.sin() + u2 * a1.sin());
+                vec::Vec {
+                    x,
+                    y,
+                    ..Default::default()
+                }
+            }
+            Aperture::Mask(mask) => {
+                let (u1, u2) = sampler.get_2d();
+                let (x, y) = mask.sample(u1, u2);
+                vec::Vec {
+                    x,
+                    y,
+                    ..Default::default()
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Camera {
+    pub orig: vec::Point,
+    pub llc: vec::Point,
+    pub hori: vec::Vec,
+    pub vert: vec::Vec,
+    pub w: vec::Vec,
+    pub u: vec::Vec,
+    pub v: vec::Vec,
+    pub lr: f32,
+    pub aperture: Aperture,
+    pub ar: f32,
+    pub fd: f32,
+    // Exposure in stops; samples are scaled by 2^ev.
+    pub ev: f32,
+    // Lens shift, as fractions of the frame width and height, moves the
+    // frame across the image plane without turning the camera.
+    pub shift_x: f32,
+    pub shift_y: f32,
+    // Scheimpflug tilt and swing, in radians, of the focal plane about the
+    // point fd along the view axis. Positive tilt moves the top of the
+    // plane away from the camera, positive swing the right side.
+    pub tilt: f32,
+    pub swing: f32,
+    pub projection: Projection,
+    pub stereo: Stereo,
+    // Traces rays through a real lens instead of the thin lens model.
+    pub lens: Option<lens::LensSystem>,
+}
+
+fn basis(lf: &vec::Point, la: &vec::Point, vup: &vec::Vec) -> (vec::Vec, vec::Vec, vec::Vec) {
+    let w = lf.sub(&[*la]).unit();
+    let u = vup.cross(&w).unit();
+    let v = w.cross(&u);
+    (u, v, w)
+}
+
+pub fn init(
+    lf: &vec::Point,
+    la: &vec::Point,
+    vup: &vec::Vec,
+    vfov: f32,
+    ar: f32,
+    ap: f32,
+    fd: f32,
+) -> Camera {
+    let theta = std::f32::consts::PI * vfov / 180.0;
+    let h = (theta / 2.0).tan();
+    let vh = 2.0 * h;
+    let vw = ar * vh;
+
+    let (u, v, w) = basis(lf, la, vup);
+
+    let hori = u.mulf(vw * fd);
+    let vert = v.mulf(vh * fd);
+    let llc = lf.sub(&[hori.divf(2.0), vert.divf(2.0), w.mulf(fd)]);
+    let lr = ap / 2.0;
+
+    Camera {
+        orig: *lf,
+        llc,
+        hori,
+        vert,
+        w,
+        u,
+        v,
+        lr,
+        ar,
+        fd,
+        projection: Projection::Perspective,
+        ..Default::default()
+    }
+}
+
+// Perspective camera described like a real one: focal length and sensor
+// width in millimetres and the f-number. Scene units are taken to be metres.
+// The sensor width spans the image width.
+#[allow(clippy::too_many_arguments)]
+pub fn init_physical(
+    lf: &vec::Point,
+    la: &vec::Point,
+    vup: &vec::Vec,
+    focal_length: f32,
+    f_number: f32,
+    sensor_width: f32,
+    ar: f32,
+    fd: f32,
+) -> Camera {
+    let sensor_height = sensor_width / ar;
+    let vfov = 2.0 * (sensor_height / (2.0 * focal_length)).atan().to_degrees();
+    let ap = focal_length / f_number / 1000.0;
+    init(lf, la, vup, vfov, ar, ap, fd)
+}
+
+// Exposure, in stops, for the given f-number, shutter time in seconds and
+// ISO. It is calibrated by the sunny 16 rule: f/16 at 1/ISO seconds gives
+// 0, i.e. the renderer's daylight sky is exposed as is.
+pub fn exposure_ev(f_number: f32, shutter: f32, iso: f32) -> f32 {
+    (shutter * iso * 256.0 / (f_number * f_number)).log2()
+}
+
+// Parallel rays along the view direction through a view rectangle of the
+// given height (in world units) centred on lf.
+pub fn init_orthographic(
+    lf: &vec::Point,
+    la: &vec::Point,
+    vup: &vec::Vec,
+    height: f32,
+    ar: f32,
+) -> Camera {
+    let (u, v, w) = basis(lf, la, vup);
+    let hori = u.mulf(ar * height);
+    let vert = v.mulf(height);
+    let llc = lf.sub(&[hori.divf(2.0), vert.divf(2.0)]);
+
+    Camera {
+        orig: *lf,
+        llc,
+        hori,
+        vert,
+        w,
+        u,
+        v,
+        ar,
+        projection: Projection::Orthographic,
+        ..Default::default()
+    }
+}
+
+// Circular fisheye; fov is in degrees and may exceed 180.
+pub fn init_fisheye(
+    lf: &vec::Point,
+    la: &vec::Point,
+    vup: &vec::Vec,
+    fov: f32,
+    mapping: FisheyeMapping,
+    ar: f32,
+) -> Camera {
+    let (u, v, w) = basis(lf, la, vup);
+    Camera {
+        orig: *lf,
+        w,
+        u,
+        v,
+        ar,
+        projection: Projection::Fisheye {
+            fov: std::f32::consts::PI * fov / 180.0,
+            mapping,
+        },
+        ..Default::default()
+    }
+}
+
+// 360 degree panorama; the image should have a 2:1 aspect ratio.
+pub fn init_equirectangular(lf: &vec::Point, la: &vec::Point, vup: &vec::Vec) -> Camera {
+    let (u, v, w) = basis(lf, la, vup);
+    Camera {
+        orig: *lf,
+        w,
+        u,
+        v,
+        ar: 2.0,
+        projection: Projection::Equirectangular,
+        ..Default::default()
+    }
+}
+
+impl Camera {
+    pub fn exposure(&self) -> f32 {
+        self.ev.exp2()
+    }
+
+    // Offsets the frame of a perspective or orthographic camera by the
+    // given fractions of its width and height.
+    pub fn shift(&mut self, x: f32, y: f32) {
+        self.llc = self.llc.add(&[
+            self.hori.mulf(x - self.shift_x),
+            self.vert.mulf(y - self.shift_y),
+        ]);
+        self.shift_x = x;
+        self.shift_y = y;
+    }
+
+    // Moves the focal plane to distance fd, keeping the field of view. A lens
+    // that cannot focus this close keeps its old focus, and the error says
+    // why.
+    pub fn refocus(&mut self, fd: f32) -> Result<(), String> {
+        let k = fd / self.fd;
+        self.hori = self.hori.mulf(k);
+        self.vert = self.vert.mulf(k);
+        self.llc = self.orig.sub(&[
+            self.hori.mulf(0.5 - self.shift_x),
+            self.vert.mulf(0.5 - self.shift_y),
+            self.w.mulf(fd),
+        ]);
+        self.fd = fd;
+        match &mut self.lens {
+            Some(lens) => lens.focus(fd * 1000.0),
+            None => Ok(()),
+        }
+    }
+
+    // Focuses on whatever is under the centre of the image, returning the
+    // new focus distance. The focus is left alone if the centre ray hits
+    // nothing.
+    pub fn autofocus(&mut self, world: &hittable::HittableList) -> Result<f32, String> {
+        if self.projection != Projection::Perspective {
+            return Err(String::from("autofocus needs a perspective camera"));
         }
+        let r = ray::Ray {
+            orig: self.orig,
+            dir: self
+                .llc
+                .add(&[self.hori.divf(2.0), self.vert.divf(2.0)])
+                .sub(&[self.orig]),
+        };
+        let mut rec: hittable::HitRecord = Default::default();
+        if !world.hit(&r, 0.001, f32::MAX, &mut rec) {
+            return Err(String::from(
+                "autofocus: nothing under the image centre, keeping focus distance",
+            ));
+        }
+        let fd = rec.p.sub(&[self.orig]).dot(&self.w.mulf(-1.0));
+        self.refocus(fd)?;
+        Ok(fd)
+    }
+
+    // Where the ray from p along d meets the focal plane.
+    fn focus_point(&self, p: &vec::Point, d: &vec::Vec) -> vec::Point {
+        let n = self
+            .w
+            .add(&[self.v.mulf(self.tilt.tan()), self.u.mulf(self.swing.tan())]);
+        let p0 = self.orig.sub(&[self.w.mulf(self.fd)]);
+        p.add(&[d.mulf(p0.sub(&[*p]).dot(&n) / d.dot(&n))])
     }
 
-    impl Camera {
-        pub fn get_ray(&self, s: f32, t: f32) -> ray::ray::Ray {
-            let rd = vec::vec::rand_unit_disk().mulf(self.lr);
-            let offset = self.u.mulf(rd.x).add(&[self.v.mulf(rd.y)]);
-            ray::ray::Ray {
-                orig: self.orig.add(&[offset]),
-                dir: self.llc.add(&[
-                    self.hori.mulf(s),
-                    self.vert.mulf(t),
-                    self.orig.mulf(-1.0),
-                    offset.mulf(-1.0),
-                ]),
+    // Film position that p is seen at by the eye that sees film position
+    // (s, t), the inverse of get_ray for pinhole perspective and
+    // orthographic cameras. None for other projections and points behind a
+    // perspective camera.
+    pub fn project(&self, p: &vec::Point, s: f32, t: f32) -> Option<(f32, f32)> {
+        let (eye, _, _) = self.stereo.eye(s, t);
+        let e = self.u.mulf(eye * self.stereo.iod / 2.0);
+        let c = self.stereo.convergence;
+        let q = match self.projection {
+            Projection::Perspective => {
+                // get_ray aims each eye through the point the centre ray
+                // meets on the convergence plane; walk back along that.
+                let d = p.sub(&[self.orig, e]);
+                let z = -d.dot(&self.w);
+                if z <= 0.0 {
+                    return None;
+                }
+                let q = self.orig.add(&[d.mulf(self.fd / z)]);
+                if c > 0.0 {
+                    q.add(&[e.mulf(self.fd / c)])
+                } else {
+                    q
+                }
+            }
+            Projection::Orthographic => {
+                let q = p.sub(&[e]);
+                if c > 0.0 {
+                    let z = -p.sub(&[self.llc]).dot(&self.w);
+                    q.add(&[e.mulf(z / c)])
+                } else {
+                    q
+                }
+            }
+            _ => return None,
+        };
+        let rel = q.sub(&[self.llc]);
+        Some(self.stereo.film(
+            eye,
+            rel.dot(&self.hori) / self.hori.len_sqrd(),
+            rel.dot(&self.vert) / self.vert.len_sqrd(),
+        ))
+    }
+
+    // World space direction from camera space components, with z pointing
+    // along the view direction.
+    fn dir(&self, x: f32, y: f32, z: f32) -> vec::Vec {
+        self.u.mulf(x).add(&[self.v.mulf(y), self.w.mulf(-z)])
+    }
+
+    // Camera ray for film position (s, t) and the weight its radiance is
+    // scaled by, which is only not one for lens system cameras.
+    pub fn generate_ray(
+        &self,
+        s: f32,
+        t: f32,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> Option<(ray::Ray, vec::Colour)> {
+        let Some(lens) = &self.lens else {
+            let one = vec::Colour {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            };
+            return self.get_ray(s, t, sampler).map(|r| (r, one));
+        };
+        // Lens space is in millimetres with the scene towards -z.
+        let (r, weight) = lens.sample(s, t, sampler)?;
+        let to_world = |p: &vec::Vec| self.u.mulf(p.x).add(&[self.v.mulf(p.y), self.w.mulf(p.z)]);
+        Some((
+            ray::Ray {
+                orig: self.orig.add(&[to_world(&r.orig).divf(1000.0)]),
+                dir: to_world(&r.dir),
+            },
+            weight,
+        ))
+    }
+
+    // Ray through film position (s, t), both in [0, 1] from the lower left
+    // of the full image (both eyes, for stereo cameras). Returns None where
+    // the projection covers no directions, e.g. outside a fisheye's image
+    // circle.
+    pub fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn sampler::Sampler) -> Option<ray::Ray> {
+        let (eye, s, t) = self.stereo.eye(s, t);
+        let e = self.u.mulf(eye * self.stereo.iod / 2.0);
+        let c = self.stereo.convergence;
+
+        let r = match self.projection {
+            Projection::Perspective => {
+                // Aim from the eye through the point the centre ray hits on
+                // the convergence plane, then focus on the focal plane.
+                let target = self.llc.add(&[self.hori.mulf(s), self.vert.mulf(t)]);
+                let eye_orig = self.orig.add(&[e]);
+                let focus = if eye == 0.0 && self.tilt == 0.0 && self.swing == 0.0 {
+                    target
+                } else if eye == 0.0 {
+                    self.focus_point(&self.orig, &target.sub(&[self.orig]))
+                } else {
+                    let d = if c > 0.0 {
+                        self.orig
+                            .add(&[target.sub(&[self.orig]).mulf(c / self.fd)])
+                            .sub(&[eye_orig])
+                    } else {
+                        target.sub(&[self.orig])
+                    };
+                    self.focus_point(&eye_orig, &d)
+                };
+
+                let rd = self.aperture.sample(sampler).mulf(self.lr);
+                let offset = self.u.mulf(rd.x).add(&[self.v.mulf(rd.y)]);
+                return Some(ray::Ray {
+                    orig: eye_orig.add(&[offset]),
+                    dir: focus.sub(&[eye_orig, offset]),
+                });
+            }
+            Projection::Orthographic => ray::Ray {
+                orig: self.llc.add(&[self.hori.mulf(s), self.vert.mulf(t)]),
+                dir: self.w.mulf(-1.0),
+            },
+            Projection::Fisheye { fov, mapping } => {
+                let x = (s - 0.5) * self.ar;
+                let y = t - 0.5;
+                let r = 2.0 * (x * x + y * y).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let theta_max = fov / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * theta_max,
+                    FisheyeMapping::Equisolid => 2.0 * (r * (theta_max / 2.0).sin()).asin(),
+                };
+                let phi = y.atan2(x);
+                ray::Ray {
+                    orig: self.orig,
+                    dir: self.dir(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    ),
+                }
+            }
+            Projection::Equirectangular => {
+                let lon = (s - 0.5) * 2.0 * std::f32::consts::PI;
+                let lat = (t - 0.5) * std::f32::consts::PI;
+                let dir = self.dir(lat.cos() * lon.sin(), lat.sin(), lat.cos() * lon.cos());
+                if eye == 0.0 {
+                    return Some(ray::Ray {
+                        orig: self.orig,
+                        dir,
+                    });
+                }
+                // Omnidirectional stereo: the eyes sit on a circle of
+                // diameter iod, offset perpendicular to the ray's heading.
+                let tangent = self.dir(lon.cos(), 0.0, -lon.sin());
+                let orig = self.orig.add(&[tangent.mulf(eye * self.stereo.iod / 2.0)]);
+                let dir = if c > 0.0 {
+                    self.orig.add(&[dir.mulf(c)]).sub(&[orig])
+                } else {
+                    dir
+                };
+                return Some(ray::Ray { orig, dir });
+            }
+        };
+
+        // Orthographic and fisheye eyes are offset copies of the mono view,
+        // toed in towards the convergence distance.
+        if eye == 0.0 {
+            return Some(r);
+        }
+        let orig = r.orig.add(&[e]);
+        let dir = if c > 0.0 {
+            r.orig.add(&[r.dir.unit().mulf(c)]).sub(&[orig])
+        } else {
+            r.dir
+        };
+        Some(ray::Ray { orig, dir })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::Sampler;
+    use crate::sampler::Samplers;
+
+    #[test]
+    fn aperture_shapes() {
+        let mut sampler = Samplers::new("independent", 7, 1).unwrap();
+        let square = Aperture::Polygon {
+            blades: 4,
+            rotation: std::f32::consts::FRAC_PI_4,
+        };
+        // Only the right half of a 4x2 mask is lit.
+        let mask = Aperture::Mask(
+            ApertureMask::new(4, 2, &[0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0]).unwrap(),
+        );
+        let h = std::f32::consts::FRAC_1_SQRT_2 + 1e-6;
+        for i in 0..1000 {
+            sampler.start_sample(0, 0, i);
+            let p = square.sample(&mut sampler);
+            assert!(p.x.abs() <= h && p.y.abs() <= h, "{p:?}");
+            let p = mask.sample(&mut sampler);
+            assert!((0.0..=1.0).contains(&p.x) && p.y.abs() <= 0.5, "{p:?}");
+        }
+        assert!(ApertureMask::new(2, 2, &[0.0; 4]).is_none());
+        assert!(ApertureMask::new(0, 2, &[1.0; 4]).is_none());
+        assert!(ApertureMask::new(2, 0, &[]).is_none());
+        assert!(ApertureMask::new(2, 2, &[1.0; 3]).is_none());
+        assert!(ApertureMask::new(2, 2, &[1.0; 5]).is_none());
+    }
+
+    #[test]
+    fn physical_camera() {
+        assert_eq!(exposure_ev(16.0, 0.01, 100.0), 0.0);
+        assert!((exposure_ev(8.0, 0.01, 100.0) - 2.0).abs() < 1e-5);
+
+        // A 50mm lens on a 36mm sensor sees about 39.6 degrees across.
+        let lf = crate::vec::Point::default();
+        let la = crate::vec::Point {
+            z: -1.0,
+            ..Default::default()
+        };
+        let vup = crate::vec::Vec {
+            y: 1.0,
+            ..Default::default()
+        };
+        let mut cam = init_physical(&lf, &la, &vup, 50.0, 2.0, 36.0, 1.0, 1.0);
+        assert!((cam.hori.len() - 0.72).abs() < 1e-4);
+
+        let mut world: crate::hittable::HittableList = Default::default();
+        world
+            .objects
+            .push(crate::hittable::Hittables::Sphere(crate::sphere::Sphere {
+                c: crate::vec::Point {
+                    z: -5.0,
+                    ..Default::default()
+                },
+                r: 1.0,
+                mat: Default::default(),
+            }));
+        let fd = cam.autofocus(&world).unwrap();
+        assert!((fd - 4.0).abs() < 1e-4, "{fd}");
+        assert!((cam.hori.len() - 2.88).abs() < 1e-3);
+
+        // Nothing to focus on keeps the focus distance.
+        assert!(cam.autofocus(&Default::default()).is_err());
+        assert_eq!(cam.fd, fd);
+    }
+
+    #[test]
+    fn tilt_shift() {
+        let lf = crate::vec::Point::default();
+        let la = crate::vec::Point {
+            z: -1.0,
+            ..Default::default()
+        };
+        let vup = crate::vec::Vec {
+            y: 1.0,
+            ..Default::default()
+        };
+        let mut cam = init(&lf, &la, &vup, 90.0, 1.0, 0.5, 2.0);
+        let mut sampler = Samplers::new("independent", 3, 1).unwrap();
+
+        // Shifting up half a frame puts the view axis at the bottom edge while
+        // the view direction stays put.
+        cam.shift(0.0, 0.5);
+        cam.lr = 0.0;
+        sampler.start_sample(0, 0, 0);
+        let r = cam.get_ray(0.5, 0.0, &mut sampler).unwrap();
+        assert!(r.dir.x.abs() < 1e-5 && r.dir.y.abs() < 1e-5, "{:?}", r.dir);
+        assert_eq!(cam.w, la.mulf(-1.0));
+
+        // With a 45 degree tilt, points at height y on the focal plane are
+        // y further away, and every aperture sample converges on them.
+        cam.shift(0.0, 0.0);
+        cam.lr = 0.25;
+        cam.tilt = std::f32::consts::FRAC_PI_4;
+        let mut focus = None;
+        for i in 0..16 {
+            sampler.start_sample(0, 0, i);
+            let r = cam.get_ray(0.5, 0.75, &mut sampler).unwrap();
+            // The pinhole ray through this film point has slope 1 / 2 and
+            // meets the plane y = -z - 2 at z = -4.
+            let p = r.at((-4.0 - r.orig.z) / r.dir.z);
+            let f = *focus.get_or_insert(p);
+            assert!(p.sub(&[f]).len() < 1e-4, "{p:?} {f:?}");
+        }
+        let f = focus.unwrap();
+        assert!((f.y - 2.0).abs() < 1e-4, "{f:?}");
+    }
+
+    #[test]
+    fn stereo() {
+        let sbs = |convergence| Stereo {
+            layout: StereoLayout::SideBySide,
+            iod: 0.2,
+            convergence,
+        };
+        assert_eq!(sbs(0.0).film_size(40, 30), (80, 30));
+        let tb = Stereo {
+            layout: StereoLayout::TopBottom,
+            ..sbs(0.0)
+        };
+        assert_eq!(tb.film_size(40, 30), (40, 60));
+        assert_eq!(Stereo::default().film_size(40, 30), (40, 30));
+        // The left eye is on the left or the top.
+        assert_eq!(sbs(0.0).eye(0.25, 0.5), (-1.0, 0.5, 0.5));
+        assert_eq!(tb.eye(0.5, 0.75), (-1.0, 0.5, 0.5));
+        assert_eq!(tb.eye(0.5, 0.25), (1.0, 0.5, 0.5));
+
+        let lf = crate::vec::Point::default();
+        let la = crate::vec::Point {
+            z: -1.0,
+            ..Default::default()
+        };
+        let vup = crate::vec::Vec {
+            y: 1.0,
+            ..Default::default()
+        };
+        let mut sampler = Samplers::new("independent", 5, 1).unwrap();
+        for mut cam in [
+            init(&lf, &la, &vup, 60.0, 1.0, 0.0, 2.0),
+            init_orthographic(&lf, &la, &vup, 2.0, 1.0),
+        ] {
+            for stereo in [sbs(0.0), sbs(3.0), tb] {
+                cam.stereo = stereo;
+                // Off axis film positions in each eye, which sit iod apart.
+                let (l, r) = match stereo.layout {
+                    StereoLayout::TopBottom => ((0.8, 0.9), (0.8, 0.4)),
+                    _ => ((0.4, 0.8), (0.9, 0.8)),
+                };
+                let left = cam.get_ray(l.0, l.1, &mut sampler).unwrap();
+                let right = cam.get_ray(r.0, r.1, &mut sampler).unwrap();
+                let sep = right.orig.sub(&[left.orig]);
+                assert!((sep.x - 0.2).abs() < 1e-5 && sep.y.abs() < 1e-5, "{sep:?}");
+
+                // Points along each eye's rays project back to where they
+                // were seen, in the full image.
+                for ((s, t), ray) in [(l, left), (r, right)] {
+                    let p = ray.at(5.0 / ray.dir.len());
+                    let (ps, pt) = cam.project(&p, s, t).unwrap();
+                    assert!((ps - s).abs() < 1e-4 && (pt - t).abs() < 1e-4, "{ps} {pt}");
+                }
             }
         }
     }
@@ -0,0 +1,238 @@
+use crate::render::luminance;
+use crate::render::Framebuffer;
+use crate::vec::Colour;
+use crate::vec::Vec;
+
+// B3 spline taps of the a-trous kernel.
+const TAPS: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Variance of the mean luminance at (i, j) from its neighbours, for pixels
+// with too few samples to have their own. Half the median absolute
+// difference between neighbouring pixels in a 5x5 window is a robust
+// estimate of the noise (as in Donoho's wavelet estimator), since edges
+// only make up a few of the differences.
+fn spatial_variance(lum: &[f32], width: i32, height: i32, i: i32, j: i32) -> f32 {
+    let (x0, x1) = ((i - 2).max(0), (i + 2).min(width - 1));
+    let (y0, y1) = ((j - 2).max(0), (j + 2).min(height - 1));
+    let at = |x: i32, y: i32| lum[(y * width + x) as usize];
+    let mut diffs = std::vec::Vec::with_capacity(40);
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            if x < x1 {
+                diffs.push((at(x + 1, y) - at(x, y)).abs());
+            }
+            if y < y1 {
+                diffs.push((at(x, y + 1) - at(x, y)).abs());
+            }
+        }
+    }
+    if diffs.is_empty() {
+        return f32::MAX;
+    }
+    let mid = diffs.len() / 2;
+    let (_, median, _) = diffs.select_nth_unstable_by(mid, f32::total_cmp);
+    // The difference of two samples has twice the variance of one.
+    let sigma = *median / (0.6745 * std::f32::consts::SQRT_2);
+    sigma * sigma
+}
+
+// Edge-avoiding a-trous wavelet filter (Dammertz et al. 2010). Each
+// iteration blurs with a 5x5 kernel whose taps are twice as far apart as
+// the last, weighting neighbours down where the normal or albedo differ,
+// or where the luminance differs by more than the noise explains. The
+// noise is tracked per pixel from the sample variance, as in SVGF, so
+// converged pixels are left alone; pixels with a single sample take it
+// from their neighbours instead.
+pub fn denoise(
+    fb: &Framebuffer,
+    normal: &Framebuffer,
+    albedo: &Framebuffer,
+    iterations: u32,
+) -> Framebuffer {
+    let (width, height) = (fb.width, fb.height);
+    let mut colour: std::vec::Vec<Colour> = fb.pixels.iter().map(|p| p.colour()).collect();
+    let lum: std::vec::Vec<f32> = colour.iter().map(luminance).collect();
+    let mut var: std::vec::Vec<f32> = fb
+        .pixels
+        .iter()
+        .enumerate()
+        .map(|(p, px)| {
+            if px.n < 2 {
+                let (i, j) = (p as i32 % width, p as i32 / width);
+                spatial_variance(&lum, width, height, i, j)
+            } else {
+                px.variance()
+            }
+        })
+        .collect();
+    let normal: std::vec::Vec<Vec> = normal
+        .pixels
+        .iter()
+        .map(|p| {
+            let n = p.colour();
+            if n.len_sqrd() > 0.0 {
+                n.unit()
+            } else {
+                n
+            }
+        })
+        .collect();
+    let albedo: std::vec::Vec<Colour> = albedo.pixels.iter().map(|p| p.colour()).collect();
+
+    // Once the taps step past the image edges only the centre is left, so
+    // the useful iterations are bounded by the bits in the image size.
+    let iterations = iterations.min(32 - width.max(height).max(1).leading_zeros());
+    for it in 0..iterations {
+        let step = 1 << it;
+        let mut next_colour = colour.clone();
+        let mut next_var = var.clone();
+        for j in 0..height {
+            for i in 0..width {
+                let p = (j * width + i) as usize;
+                let lp = luminance(&colour[p]);
+                let sigma = 4.0 * var[p].sqrt() + 1e-4;
+                let mut c = Colour::default();
+                let mut v = 0.0;
+                let mut sum = 0.0;
+                for (dy, ky) in TAPS.iter().enumerate() {
+                    let y = j + (dy as i32 - 2) * step;
+                    if y < 0 || y >= height {
+                        continue;
+                    }
+                    for (dx, kx) in TAPS.iter().enumerate() {
+                        let x = i + (dx as i32 - 2) * step;
+                        if x < 0 || x >= width {
+                            continue;
+                        }
+                        let q = (y * width + x) as usize;
+                        // Background pixels have no normal; they only
+                        // blend with each other.
+                        let wn = if normal[p].len_sqrd() == 0.0 && normal[q].len_sqrd() == 0.0 {
+                            1.0
+                        } else {
+                            normal[p].dot(&normal[q]).max(0.0).powi(128)
+                        };
+                        let da = albedo[p].sub(&[albedo[q]]);
+                        let wa = (-da.len_sqrd() / 0.01).exp();
+                        let wl = (-(lp - luminance(&colour[q])).abs() / sigma).exp();
+                        let w = kx * ky * wn * wa * wl;
+                        c = c.add(&[colour[q].mulf(w)]);
+                        v += w * w * var[q];
+                        sum += w;
+                    }
+                }
+                if sum > 0.0 {
+                    next_colour[p] = c.divf(sum);
+                    next_var[p] = v / (sum * sum);
+                }
+            }
+        }
+        colour = next_colour;
+        var = next_var;
+    }
+
+    let mut out = Framebuffer::new(width, height);
+    for ((o, px), c) in out.pixels.iter_mut().zip(&fb.pixels).zip(colour) {
+        *o = *px;
+        o.set_colour(&c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Framebuffer;
+    use crate::util::Rng;
+    use crate::vec::Colour;
+
+    #[test]
+    fn smooths_noise_but_not_edges() {
+        let (w, h) = (32, 16);
+        let mut rng = Rng::new(5);
+        let mut fb = Framebuffer::new(w, h);
+        let mut albedo = Framebuffer::new(w, h);
+        let mut normal = Framebuffer::new(w, h);
+        // Two flat grey halves of different albedo, lit with noisy samples.
+        for j in 0..h {
+            for i in 0..w {
+                let idx = (j * w + i) as usize;
+                let a = if i < w / 2 { 0.2 } else { 0.8 };
+                let grey = |v: f32| Colour { x: v, y: v, z: v };
+                for _ in 0..4 {
+                    let c = grey(a * 2.0 * rng.next_f32());
+                    fb.pixels[idx].add_sample(&c);
+                    fb.pixels[idx].sum = fb.pixels[idx].sum.add(&[c]);
+                    fb.pixels[idx].weight += 1.0;
+                }
+                albedo.pixels[idx].sum = grey(a);
+                albedo.pixels[idx].weight = 1.0;
+                normal.pixels[idx].sum = Colour {
+                    z: 1.0,
+                    ..Default::default()
+                };
+                normal.pixels[idx].weight = 1.0;
+            }
+        }
+
+        let out = denoise(&fb, &normal, &albedo, 5);
+        // Mean squared error against the expected a per half.
+        let err = |fb: &Framebuffer, half: std::ops::Range<i32>, a: f32| {
+            let mut e = 0.0;
+            for j in 0..h {
+                for i in half.clone() {
+                    e += (fb.at(i, j).colour().x - a).powi(2);
+                }
+            }
+            e / (h * half.len() as i32) as f32
+        };
+        for (half, a) in [(0..w / 2, 0.2), (w / 2..w, 0.8)] {
+            let (before, after) = (err(&fb, half.clone(), a), err(&out, half, a));
+            assert!(after < before / 4.0, "{before} -> {after}");
+        }
+    }
+
+    #[test]
+    fn estimates_noise_at_one_sample() {
+        let (w, h) = (32, 16);
+        let mut rng = Rng::new(9);
+        let mut fb = Framebuffer::new(w, h);
+        let mut flat = Framebuffer::new(w, h);
+        // A shadow edge: the guides are the same on both sides, so only the
+        // noise estimate keeps the halves apart.
+        for j in 0..h {
+            for i in 0..w {
+                let idx = (j * w + i) as usize;
+                let a = if i < w / 2 { 0.2 } else { 0.8 };
+                let v = a + 0.05 * (rng.next_f32() - 0.5);
+                let c = Colour { x: v, y: v, z: v };
+                fb.pixels[idx].add_sample(&c);
+                fb.pixels[idx].sum = c;
+                fb.pixels[idx].weight = 1.0;
+                flat.pixels[idx].sum = Colour {
+                    z: 1.0,
+                    ..Default::default()
+                };
+                flat.pixels[idx].weight = 1.0;
+            }
+        }
+
+        let out = denoise(&fb, &flat, &flat, 5);
+        for j in 0..h {
+            let (l, r) = (out.at(w / 2 - 1, j).colour().x, out.at(w / 2, j).colour().x);
+            assert!(l < 0.25 && r > 0.75, "{l} {r}");
+        }
+
+        // Iterations past the image size change nothing, however many are
+        // asked for.
+        let colours = |fb: &Framebuffer| {
+            fb.pixels
+                .iter()
+                .map(|p| p.colour())
+                .collect::<std::vec::Vec<_>>()
+        };
+        let six = colours(&denoise(&fb, &flat, &flat, 6));
+        assert_eq!(six, colours(&denoise(&fb, &flat, &flat, 40)));
+        assert_eq!(six, colours(&denoise(&fb, &flat, &flat, u32::MAX)));
+    }
+}
@@ -1,49 +1,64 @@
-pub mod dielectric {
-    use crate::hittable;
-    use crate::material;
-    use crate::rand_f32;
-    use crate::ray;
-    use crate::vec;
+use crate::hittable;
+use crate::material;
+use crate::ray;
+use crate::sampler;
+use crate::vec;
 
-    fn reflectance(c: f32, ri: f32) -> f32 {
-        let mut r = (1.0 - ri) / (1.0 + ri);
-        r = r * r;
-        r + (1.0 - r) * ((1.0 - c).powi(5))
-    }
+fn reflectance(c: f32, ri: f32) -> f32 {
+    let mut r = (1.0 - ri) / (1.0 + ri);
+    r = r * r;
+    r + (1.0 - r) * ((1.0 - c).powi(5))
+}
+
+#[derive(Copy, Clone, Default, PartialEq)]
+pub struct Dielectric {
+    pub ir: f32,
+}
 
-    #[derive(Copy, Clone, Default)]
-    pub struct Dielectric {
-        pub ir: f32,
+impl material::Material for Dielectric {
+    fn scatter(
+        &self,
+        r: &ray::Ray,
+        rec: &hittable::HitRecord,
+        att: &mut vec::Colour,
+        scattered: &mut ray::Ray,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> Option<material::Lobe> {
+        *att = vec::Colour {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        };
+        let rr = if rec.front_face {
+            1.0 / self.ir
+        } else {
+            self.ir
+        };
+        let u = r.dir.unit();
+        let c = u.mulf(-1.0).dot(&rec.n).min(1.0);
+        let s = (1.0 - c * c).sqrt();
+        // Schlick's approximation takes the cosine on the outside of the
+        // glass, so that light is reflected alike going either way.
+        let c_out = if rr > 1.0 {
+            (1.0 - rr * rr * s * s).max(0.0).sqrt()
+        } else {
+            c
+        };
+        let (dir, lobe) = if rr * s > 1.0 || reflectance(c_out, rr) > sampler.get_1d() {
+            (u.reflect(&rec.n), material::Lobe::Specular)
+        } else {
+            (u.refract(&rec.n, rr), material::Lobe::Transmission)
+        };
+        *scattered = ray::Ray { orig: rec.p, dir };
+        Some(lobe)
     }
 
-    impl material::material::Material for Dielectric {
-        fn scatter(
-            &self,
-            r: &ray::ray::Ray,
-            rec: &hittable::hittable::HitRecord,
-            att: &mut vec::vec::Colour,
-            scattered: &mut ray::ray::Ray,
-        ) -> bool {
-            *att = vec::vec::Colour {
-                x: 1.0,
-                y: 1.0,
-                z: 1.0,
-            };
-            let rr = if rec.front_face {
-                1.0 / self.ir
-            } else {
-                self.ir
-            };
-            let u = r.dir.unit();
-            let c = u.mulf(-1.0).dot(&rec.n).min(1.0);
-            let s = (1.0 - c * c).sqrt();
-            let dir = if rr * s > 1.0 || reflectance(c, rr) > rand_f32() {
-                u.reflect(&rec.n)
-            } else {
-                u.refract(&rec.n, rr)
-            };
-            *scattered = ray::ray::Ray { orig: rec.p, dir };
-            true
+    // Clear glass passes all light through.
+    fn albedo(&self) -> vec::Colour {
+        vec::Colour {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
         }
     }
 }
@@ -1,66 +1,193 @@
-pub mod hittable {
-    use crate::material;
-    use crate::ray;
-    use crate::sphere;
-    use crate::vec;
-
-    #[derive(Copy, Clone, Default)]
-    pub struct HitRecord {
-        pub p: vec::vec::Vec,
-        pub n: vec::vec::Vec,
-        pub mat: material::material::Materials,
-        pub t: f32,
-        pub front_face: bool,
+use std::sync::Arc;
+
+use crate::bvh;
+use crate::material;
+use crate::ray;
+use crate::sphere;
+use crate::stats;
+use crate::vec;
+
+#[derive(Clone, Default)]
+pub struct HitRecord {
+    pub p: vec::Vec,
+    pub n: vec::Vec,
+    pub mat: material::Materials,
+    pub t: f32,
+    pub front_face: bool,
+    // Index of the object hit in its HittableList.
+    pub object: usize,
+    // Surface coordinates of the hit, each in [0, 1].
+    pub u: f32,
+    pub v: f32,
+}
+
+impl HitRecord {
+    pub fn set_face_norm(&mut self, r: &ray::Ray, out_n: &vec::Vec) {
+        self.front_face = r.dir.dot(out_n) < 0.0;
+        self.n = if self.front_face {
+            *out_n
+        } else {
+            out_n.mulf(-1.0)
+        };
+    }
+}
+
+pub trait Hittable {
+    fn hit(&self, r: &ray::Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool;
+
+    fn bounding_box(&self) -> bvh::Aabb;
+
+    // Material of the whole object, for material IDs. Objects with more
+    // than one material can leave it unset.
+    fn material(&self) -> material::Materials {
+        Default::default()
+    }
+}
+
+#[derive(Clone)]
+pub enum Hittables {
+    Sphere(sphere::Sphere),
+    Custom(Custom),
+}
+
+impl Hittables {
+    pub fn custom(shape: impl Hittable + Send + Sync + 'static) -> Hittables {
+        Hittables::Custom(Custom {
+            shape: Arc::new(shape),
+            offset: Default::default(),
+        })
     }
 
-    impl HitRecord {
-        pub fn set_face_norm(&mut self, r: &ray::ray::Ray, out_n: &vec::vec::Vec) {
-            self.front_face = r.dir.dot(out_n) < 0.0;
-            self.n = if self.front_face {
-                *out_n
-            } else {
-                out_n.mulf(-1.0)
-            };
+    pub fn translate(&mut self, offset: &vec::Vec) {
+        match self {
+            Hittables::Sphere(sphere) => sphere.c = sphere.c.add(&[*offset]),
+            Hittables::Custom(custom) => custom.offset = custom.offset.add(&[*offset]),
         }
     }
+}
 
-    pub trait Hittable {
-        fn hit(&self, r: &ray::ray::Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool;
+// A shape defined outside this crate, shared between the copies of a scene
+// made for each frame and moved by offset when animated.
+#[derive(Clone)]
+pub struct Custom {
+    pub shape: Arc<dyn Hittable + Send + Sync>,
+    pub offset: vec::Vec,
+}
+
+impl Hittable for Custom {
+    fn hit(&self, r: &ray::Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let moved = ray::Ray {
+            orig: r.orig.sub(&[self.offset]),
+            dir: r.dir,
+        };
+        if !self.shape.hit(&moved, t_min, t_max, rec) {
+            return false;
+        }
+        rec.p = rec.p.add(&[self.offset]);
+        true
     }
 
-    pub enum Hittables {
-        Sphere(sphere::sphere::Sphere),
+    fn bounding_box(&self) -> bvh::Aabb {
+        let b = self.shape.bounding_box();
+        bvh::Aabb {
+            min: b.min.add(&[self.offset]),
+            max: b.max.add(&[self.offset]),
+        }
     }
 
-    impl Hittable for Hittables {
-        fn hit(&self, r: &ray::ray::Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
-            match self {
-                Hittables::Sphere(sphere) => sphere.hit(r, t_min, t_max, rec),
-            }
+    fn material(&self) -> material::Materials {
+        self.shape.material()
+    }
+}
+
+impl Hittable for Hittables {
+    fn hit(&self, r: &ray::Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        match self {
+            Hittables::Sphere(sphere) => sphere.hit(r, t_min, t_max, rec),
+            Hittables::Custom(custom) => custom.hit(r, t_min, t_max, rec),
+        }
+    }
+
+    fn bounding_box(&self) -> bvh::Aabb {
+        match self {
+            Hittables::Sphere(sphere) => sphere.bounding_box(),
+            Hittables::Custom(custom) => custom.bounding_box(),
         }
     }
 
-    #[derive(Default)]
-    pub struct HittableList {
-        pub objects: std::vec::Vec<Hittables>,
+    fn material(&self) -> material::Materials {
+        match self {
+            Hittables::Sphere(sphere) => sphere.material(),
+            Hittables::Custom(custom) => custom.material(),
+        }
     }
+}
 
-    impl Hittable for HittableList {
-        fn hit(&self, r: &ray::ray::Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
-            let mut temp_rec: HitRecord = Default::default();
+#[derive(Clone, Default)]
+pub struct HittableList {
+    pub objects: std::vec::Vec<Hittables>,
+    // Built by build_bvh. It is ignored once objects are added or removed,
+    // but objects moved in place need build_bvh again.
+    bvh: Option<bvh::Bvh>,
+}
 
-            let mut hit = false;
-            let mut closest = t_max;
+impl HittableList {
+    pub fn build_bvh(&mut self) {
+        self.bvh = Some(bvh::Bvh::new(&self.objects));
+    }
 
-            for obj in &self.objects[..] {
-                if obj.hit(r, t_min, closest, &mut temp_rec) {
-                    hit = true;
-                    closest = temp_rec.t;
-                    *rec = temp_rec;
-                }
+    // Like hit, also counting the bounding boxes and objects tested.
+    pub fn hit_cost(
+        &self,
+        r: &ray::Ray,
+        t_min: f32,
+        t_max: f32,
+        rec: &mut HitRecord,
+        cost: &mut u32,
+    ) -> bool {
+        let mut tests = 0;
+        let bvh = self.bvh.as_ref();
+        let hit = match bvh.filter(|b| b.objects() == self.objects.len()) {
+            Some(bvh) => bvh.hit(&self.objects, r, t_min, t_max, rec, &mut tests),
+            None => {
+                tests = self.objects.len() as u32;
+                self.hit_linear(r, t_min, t_max, rec)
             }
+        };
+        stats::add(&stats::INTERSECTION_TESTS, tests as u64);
+        *cost += tests;
+        hit
+    }
+
+    fn hit_linear(&self, r: &ray::Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let mut temp_rec: HitRecord = Default::default();
+
+        let mut hit = false;
+        let mut closest = t_max;
 
-            hit
+        for (i, obj) in self.objects.iter().enumerate() {
+            if obj.hit(r, t_min, closest, &mut temp_rec) {
+                hit = true;
+                closest = temp_rec.t;
+                *rec = temp_rec.clone();
+                rec.object = i;
+            }
         }
+
+        hit
+    }
+}
+
+impl Hittable for HittableList {
+    fn hit(&self, r: &ray::Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        self.hit_cost(r, t_min, t_max, rec, &mut 0)
+    }
+
+    fn bounding_box(&self) -> bvh::Aabb {
+        self.objects
+            .iter()
+            .map(|o| o.bounding_box())
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or_default()
     }
 }
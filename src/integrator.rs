@@ -0,0 +1,142 @@
+use crate::hittable::HitRecord;
+use crate::hittable::Hittable;
+use crate::hittable::HittableList;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::render;
+use crate::sampler::Sampler;
+use crate::settings::Settings;
+use crate::stats;
+use crate::vec;
+use crate::vec::Colour;
+
+// What each camera ray computes. Everything but the path tracer is a
+// quick look at the scene for diagnosing it.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Integrator {
+    #[default]
+    Path,
+    // Surface normal, mapped from [-1, 1] to [0, 1].
+    Normals,
+    // Surface coordinates in red and green.
+    Uv,
+    // Distance from the camera, half grey at the focus distance.
+    Depth,
+    // Fraction of the hemisphere above the surface that is open within
+    // the AO distance.
+    Ao,
+    // Surface colour with no lighting.
+    Albedo,
+    // Heatmap of the bounces path tracing took, up to the maximum depth.
+    Bounces,
+    // Heatmap of the boxes and objects tested to find the first hit, on a
+    // log scale up to twice the number of objects.
+    Cost,
+}
+
+impl std::str::FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Integrator, String> {
+        match s {
+            "path" => Ok(Integrator::Path),
+            "normals" => Ok(Integrator::Normals),
+            "uv" => Ok(Integrator::Uv),
+            "depth" => Ok(Integrator::Depth),
+            "ao" => Ok(Integrator::Ao),
+            "albedo" => Ok(Integrator::Albedo),
+            "bounces" => Ok(Integrator::Bounces),
+            "cost" => Ok(Integrator::Cost),
+            _ => Err(format!("unknown integrator: {s}")),
+        }
+    }
+}
+
+// Blue through cyan, green and yellow to red as t goes from 0 to 1.
+pub fn heat(t: f32) -> Colour {
+    let t = t.clamp(0.0, 1.0) * 4.0;
+    let ramp = |a: f32| (t - a).clamp(0.0, 1.0);
+    Colour {
+        x: ramp(2.0),
+        y: ramp(0.0) - ramp(3.0),
+        z: 1.0 - ramp(1.0),
+    }
+}
+
+fn grey(v: f32) -> Colour {
+    Colour { x: v, y: v, z: v }
+}
+
+// Colour of camera ray r under one of the debug integrators. Depth is
+// scaled by focus_dist.
+pub fn debug_colour(
+    integrator: Integrator,
+    r: &Ray,
+    world: &HittableList,
+    settings: &Settings,
+    focus_dist: f32,
+    sampler: &mut dyn Sampler,
+) -> Colour {
+    if integrator == Integrator::Bounces {
+        let (_, _, bounces, _) =
+            render::trace(r, world, settings.max_depth, settings.rr_depth, sampler);
+        return heat(bounces as f32 / settings.max_depth.max(1) as f32);
+    }
+
+    let mut rec: HitRecord = Default::default();
+    let mut cost = 0;
+    let hit = world.hit_cost(r, 0.001, f32::MAX, &mut rec, &mut cost);
+    match integrator {
+        Integrator::Cost => {
+            let max = 2.0 * world.objects.len().max(1) as f32;
+            return heat((1.0 + cost as f32).ln() / (1.0 + max).ln());
+        }
+        Integrator::Albedo if !hit => return render::background(r),
+        _ if !hit => return Default::default(),
+        _ => {}
+    }
+    match integrator {
+        Integrator::Normals => rec.n.add(&[grey(1.0)]).mulf(0.5),
+        Integrator::Uv => Colour {
+            x: rec.u,
+            y: rec.v,
+            z: 0.0,
+        },
+        Integrator::Depth => grey(focus_dist / (focus_dist + rec.t * r.dir.len())),
+        Integrator::Ao => {
+            let mut dir = rec.n.add(&[vec::rand_unit(sampler)]);
+            if dir.near_zero() {
+                dir = rec.n;
+            }
+            let ao = Ray { orig: rec.p, dir };
+            stats::add(&stats::SHADOW_RAYS, 1);
+            let t_max = settings.ao_distance / dir.len();
+            let mut occluder: HitRecord = Default::default();
+            if world.hit(&ao, 0.001, t_max, &mut occluder) {
+                Default::default()
+            } else {
+                grey(1.0)
+            }
+        }
+        Integrator::Albedo => rec.mat.albedo(),
+        _ => Default::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heatmap() {
+        assert_eq!("ao".parse::<Integrator>(), Ok(Integrator::Ao));
+        assert!("whitted".parse::<Integrator>().is_err());
+
+        let blue = heat(0.0);
+        assert_eq!((blue.x, blue.y, blue.z), (0.0, 0.0, 1.0));
+        let green = heat(0.5);
+        assert_eq!((green.x, green.y, green.z), (0.0, 1.0, 0.0));
+        let red = heat(2.0);
+        assert_eq!((red.x, red.y, red.z), (1.0, 0.0, 0.0));
+    }
+}
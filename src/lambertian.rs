@@ -1,29 +1,33 @@
-pub mod lambertian {
-    use crate::hittable;
-    use crate::material;
-    use crate::ray;
-    use crate::vec;
+use crate::hittable;
+use crate::material;
+use crate::ray;
+use crate::sampler;
+use crate::vec;
 
-    #[derive(Copy, Clone, Default)]
-    pub struct Lambertian {
-        pub albedo: vec::vec::Colour,
-    }
+#[derive(Copy, Clone, Default, PartialEq)]
+pub struct Lambertian {
+    pub albedo: vec::Colour,
+}
 
-    impl material::material::Material for Lambertian {
-        fn scatter(
-            &self,
-            _r: &ray::ray::Ray,
-            rec: &hittable::hittable::HitRecord,
-            att: &mut vec::vec::Colour,
-            scattered: &mut ray::ray::Ray,
-        ) -> bool {
-            let mut dir = rec.n.add(&[vec::vec::rand_unit()]);
-            if dir.near_zero() {
-                dir = rec.n;
-            }
-            *scattered = ray::ray::Ray { orig: rec.p, dir };
-            *att = self.albedo;
-            true
+impl material::Material for Lambertian {
+    fn scatter(
+        &self,
+        _r: &ray::Ray,
+        rec: &hittable::HitRecord,
+        att: &mut vec::Colour,
+        scattered: &mut ray::Ray,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> Option<material::Lobe> {
+        let mut dir = rec.n.add(&[vec::rand_unit(sampler)]);
+        if dir.near_zero() {
+            dir = rec.n;
         }
+        *scattered = ray::Ray { orig: rec.p, dir };
+        *att = self.albedo;
+        Some(material::Lobe::Diffuse)
+    }
+
+    fn albedo(&self) -> vec::Colour {
+        self.albedo
     }
 }
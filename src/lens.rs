@@ -0,0 +1,489 @@
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec::Colour;
+use crate::vec::Vec;
+
+// One spherical refracting surface, or the aperture stop when the radius is
+// 0. Lengths are in millimetres and follow the usual lens prescription
+// layout: surfaces are listed from the object side, the radius is positive
+// when the centre of curvature lies towards the film, the thickness is the
+// axial distance to the next surface and ior is the index of the medium
+// behind the surface (0 or 1 for air). An Abbe number of 0 means the
+// medium has no dispersion.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Element {
+    pub radius: f32,
+    pub thickness: f32,
+    pub ior: f32,
+    pub aperture: f32,
+    pub abbe: f32,
+}
+
+// Wavelengths, in nm, traced for the red, green and blue channels.
+const WAVELENGTHS: [f32; 3] = [610.0, 550.0, 465.0];
+
+impl Element {
+    // Index of refraction at wavelength lambda, from a Cauchy fit through
+    // the d line index and the Abbe number.
+    fn ior_at(&self, lambda: Option<f32>) -> f32 {
+        let n = if self.ior == 0.0 { 1.0 } else { self.ior };
+        match lambda {
+            Some(l) if self.abbe > 0.0 => {
+                let (d, f, c) = (587.6f32, 486.1f32, 656.3f32);
+                let b = (n - 1.0) / self.abbe / (1.0 / (f * f) - 1.0 / (c * c));
+                n + b * (1.0 / (l * l) - 1.0 / (d * d))
+            }
+            _ => n,
+        }
+    }
+}
+
+// Parses a lens description: one surface per line as
+//   radius thickness ior aperture [abbe]
+// with '#' starting a comment.
+pub fn parse(src: &str) -> Result<std::vec::Vec<Element>, String> {
+    let mut elements = std::vec::Vec::new();
+    for (n, line) in src.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let words: std::vec::Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        if words.len() < 4 || words.len() > 5 {
+            return Err(format!("line {}: expected 4 or 5 numbers", n + 1));
+        }
+        let mut v = [0.0; 5];
+        for (o, w) in v.iter_mut().zip(&words) {
+            *o = w
+                .parse()
+                .map_err(|_| format!("line {}: invalid number: {w}", n + 1))?;
+        }
+        elements.push(Element {
+            radius: v[0],
+            thickness: v[1],
+            ior: v[2],
+            aperture: v[3],
+            abbe: v[4],
+        });
+    }
+    if elements.is_empty() {
+        return Err(String::from("no lens elements"));
+    }
+    Ok(elements)
+}
+
+pub fn load(path: &str) -> Result<std::vec::Vec<Element>, String> {
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    parse(&src).map_err(|e| format!("{path}: {e}"))
+}
+
+fn refract(d: &Vec, n: &Vec, eta: f32) -> Option<Vec> {
+    let d = d.unit();
+    let cos_i = -d.dot(n);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t > 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(d.mulf(eta).add(&[n.mulf(eta * cos_i - cos_t)]))
+}
+
+// Number of film radius intervals the exit pupil is tabulated for.
+const PUPIL_BINS: usize = 64;
+
+// Bounds of the exit pupil on the rear vertex plane, for film points on
+// the +x axis.
+#[derive(Copy, Clone, Debug)]
+struct Bounds {
+    x0: f32,
+    x1: f32,
+    y0: f32,
+    y1: f32,
+}
+
+impl Bounds {
+    fn area(&self) -> f32 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+}
+
+// A stack of lens elements in front of a film. Lens space has the film on
+// the z = 0 plane, centred on the axis, with the scene towards -z.
+#[derive(Clone, Debug, Default)]
+pub struct LensSystem {
+    elements: std::vec::Vec<Element>,
+    // Axial position of each surface's vertex.
+    z: std::vec::Vec<f32>,
+    film_width: f32,
+    film_height: f32,
+    // Exit pupil bounds per film radius interval, None where no light
+    // gets through.
+    pupil: std::vec::Vec<Option<Bounds>>,
+    // Area of the exit pupil seen from the film centre, so that the
+    // centre of the image has unit weight.
+    pupil_area: f32,
+    dispersive: bool,
+}
+
+impl LensSystem {
+    // Places the film behind the lens so that objects fd millimetres in
+    // front of the film are in focus.
+    pub fn new(
+        elements: std::vec::Vec<Element>,
+        film_width: f32,
+        film_height: f32,
+        fd: f32,
+    ) -> Result<LensSystem, String> {
+        let dispersive = elements.iter().any(|e| e.abbe > 0.0);
+        let mut lens = LensSystem {
+            elements,
+            film_width,
+            film_height,
+            dispersive,
+            ..Default::default()
+        };
+        lens.focus(fd)?;
+        Ok(lens)
+    }
+
+    fn layout(&mut self, film_distance: f32) {
+        let mut z = -film_distance;
+        self.z = vec![0.0; self.elements.len()];
+        for i in (0..self.elements.len()).rev() {
+            self.z[i] = z;
+            if i > 0 {
+                z -= self.elements[i - 1].thickness;
+            }
+        }
+    }
+
+    fn rear_z(&self) -> f32 {
+        self.z[self.z.len() - 1]
+    }
+
+    fn rear_radius(&self) -> f32 {
+        self.elements[self.elements.len() - 1].aperture / 2.0
+    }
+
+    // Index of the medium behind surface i, with -1 the air in front.
+    fn ior(&self, i: isize, lambda: Option<f32>) -> f32 {
+        if i < 0 {
+            1.0
+        } else {
+            self.elements[i as usize].ior_at(lambda)
+        }
+    }
+
+    // Traces a ray through surface i, returning None if it misses the
+    // surface, is blocked by its aperture or is totally reflected.
+    fn surface(&self, i: usize, r: &Ray, lambda: Option<f32>) -> Option<Ray> {
+        let e = &self.elements[i];
+        let zv = self.z[i];
+        // Intersect from where the ray crosses the vertex plane; origins far
+        // out in the scene would otherwise lose the sphere to rounding.
+        let d = r.dir.unit();
+        let t0 = (zv - r.orig.z) / d.z;
+        if !t0.is_finite() {
+            return None;
+        }
+        let o = r.orig.add(&[d.mulf(t0)]);
+        let (p, n) = if e.radius == 0.0 {
+            if t0 <= 0.0 {
+                return None;
+            }
+            (o, None)
+        } else {
+            let c = Vec {
+                z: zv + e.radius,
+                ..Default::default()
+            };
+            let oc = o.sub(&[c]);
+            let b = oc.dot(&d);
+            let disc = b * b - (oc.len_sqrd() - e.radius * e.radius);
+            if disc < 0.0 {
+                return None;
+            }
+            // The vertex side of the sphere is the nearer one when the ray
+            // travels towards the centre of curvature.
+            let nearer = (d.z > 0.0) != (e.radius < 0.0);
+            let t = if nearer {
+                -b - disc.sqrt()
+            } else {
+                -b + disc.sqrt()
+            };
+            if t0 + t <= 0.0 {
+                return None;
+            }
+            let p = o.add(&[d.mulf(t)]);
+            (p, Some(p.sub(&[c]).unit()))
+        };
+        if p.x * p.x + p.y * p.y > e.aperture * e.aperture / 4.0 {
+            return None;
+        }
+        let Some(n) = n else {
+            return Some(Ray {
+                orig: p,
+                dir: r.dir,
+            });
+        };
+        let n = if n.dot(&r.dir) > 0.0 { n.mulf(-1.0) } else { n };
+        let i = i as isize;
+        let eta = if r.dir.z < 0.0 {
+            self.ior(i, lambda) / self.ior(i - 1, lambda)
+        } else {
+            self.ior(i - 1, lambda) / self.ior(i, lambda)
+        };
+        Some(Ray {
+            orig: p,
+            dir: refract(&r.dir, &n, eta)?,
+        })
+    }
+
+    // Traces a ray from the film out through the front element.
+    pub fn trace_from_film(&self, r: &Ray, lambda: Option<f32>) -> Option<Ray> {
+        (0..self.elements.len())
+            .rev()
+            .try_fold(*r, |r, i| self.surface(i, &r, lambda))
+    }
+
+    // Traces a ray from the scene in through the rear element.
+    pub fn trace_from_scene(&self, r: &Ray, lambda: Option<f32>) -> Option<Ray> {
+        (0..self.elements.len()).try_fold(*r, |r, i| self.surface(i, &r, lambda))
+    }
+
+    // Moves the film so that a point on the axis fd millimetres in front of
+    // it is imaged onto the film, then tabulates the exit pupil. Moving the
+    // lens changes the object distance, so the film distance is found by
+    // fixed point iteration.
+    pub fn focus(&mut self, fd: f32) -> Result<(), String> {
+        let mut film_distance = 0.0;
+        let mut err = f32::MAX;
+        for _ in 0..16 {
+            self.layout(film_distance);
+            // A paraxial ray from the object point to the edge of a small
+            // zone around the axis of the front element.
+            let h = self.elements[0].aperture * 0.01;
+            let r = Ray {
+                orig: Vec {
+                    z: -fd,
+                    ..Default::default()
+                },
+                dir: Vec {
+                    x: h,
+                    y: 0.0,
+                    z: self.z[0] + fd,
+                },
+            };
+            let out = self
+                .trace_from_scene(&r, None)
+                .filter(|out| out.dir.x < 0.0)
+                .ok_or(format!("lens cannot focus at {} mm", fd))?;
+            err = out.orig.z - out.orig.x * out.dir.z / out.dir.x;
+            film_distance += err;
+            if err.abs() < 1e-4 {
+                break;
+            }
+        }
+        if film_distance <= 0.0 || err.abs() > 1e-2 {
+            return Err(format!("lens cannot focus at {} mm", fd));
+        }
+        self.layout(film_distance);
+        self.tabulate_pupil();
+        Ok(())
+    }
+
+    fn tabulate_pupil(&mut self) {
+        const FILM_SAMPLES: usize = 8;
+        const GRID: usize = 32;
+        let half_diagonal = self.film_width.hypot(self.film_height) / 2.0;
+        // The rear surface bulges past its vertex plane, so search a
+        // little wider than its aperture.
+        let extent = 1.5 * self.rear_radius();
+        let cell = 2.0 * extent / GRID as f32;
+        let rear_z = self.rear_z();
+
+        self.pupil.clear();
+        self.pupil_area = 0.0;
+        for k in 0..PUPIL_BINS {
+            let mut bounds: Option<Bounds> = None;
+            let mut passed = 0;
+            for f in 0..FILM_SAMPLES {
+                let x = half_diagonal * (k as f32 + (f as f32 + 0.5) / FILM_SAMPLES as f32)
+                    / PUPIL_BINS as f32;
+                let film = Vec {
+                    x,
+                    ..Default::default()
+                };
+                for gy in 0..GRID {
+                    for gx in 0..GRID {
+                        let p = Vec {
+                            x: -extent + (gx as f32 + 0.5) * cell,
+                            y: -extent + (gy as f32 + 0.5) * cell,
+                            z: rear_z,
+                        };
+                        let r = Ray {
+                            orig: film,
+                            dir: p.sub(&[film]),
+                        };
+                        if self.trace_from_film(&r, None).is_none() {
+                            continue;
+                        }
+                        passed += 1;
+                        bounds = Some(match bounds {
+                            None => Bounds {
+                                x0: p.x,
+                                x1: p.x,
+                                y0: p.y,
+                                y1: p.y,
+                            },
+                            Some(b) => Bounds {
+                                x0: b.x0.min(p.x),
+                                x1: b.x1.max(p.x),
+                                y0: b.y0.min(p.y),
+                                y1: b.y1.max(p.y),
+                            },
+                        });
+                    }
+                }
+            }
+            if k == 0 {
+                self.pupil_area = passed as f32 * cell * cell / FILM_SAMPLES as f32;
+            }
+            // Grid points are cell centres; grow by a cell so the bounds
+            // don't clip the pupil.
+            self.pupil.push(bounds.map(|b| Bounds {
+                x0: b.x0 - cell,
+                x1: b.x1 + cell,
+                y0: b.y0 - cell,
+                y1: b.y1 + cell,
+            }));
+        }
+    }
+
+    // Ray leaving the lens for film position (s, t), both in [0, 1] from
+    // the lower left of the image, in lens space. The film is flipped as
+    // the lens forms an inverted image. The weight accounts for the
+    // cos^4 falloff and the size of the exit pupil, relative to the image
+    // centre, and for dispersive lenses carries the single traced
+    // channel. Returns None for rays blocked inside the lens.
+    pub fn sample(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Option<(Ray, Colour)> {
+        let film = Vec {
+            x: (0.5 - s) * self.film_width,
+            y: (0.5 - t) * self.film_height,
+            z: 0.0,
+        };
+        let r = film.x.hypot(film.y);
+        let half_diagonal = self.film_width.hypot(self.film_height) / 2.0;
+        let k = ((r / half_diagonal * PUPIL_BINS as f32) as usize).min(PUPIL_BINS - 1);
+        let b = self.pupil[k]?;
+
+        let (u1, u2) = sampler.get_2d();
+        let px = b.x0 + u1 * (b.x1 - b.x0);
+        let py = b.y0 + u2 * (b.y1 - b.y0);
+        let (sin, cos) = if r > 0.0 {
+            (film.y / r, film.x / r)
+        } else {
+            (0.0, 1.0)
+        };
+        let p = Vec {
+            x: cos * px - sin * py,
+            y: sin * px + cos * py,
+            z: self.rear_z(),
+        };
+        let channel = if self.dispersive {
+            Some(((sampler.get_1d() * 3.0) as usize).min(2))
+        } else {
+            None
+        };
+        let r = Ray {
+            orig: film,
+            dir: p.sub(&[film]),
+        };
+        let out = self.trace_from_film(&r, channel.map(|c| WAVELENGTHS[c]))?;
+
+        let cos_theta = r.dir.unit().z;
+        let w = cos_theta.powi(4) * b.area() / self.pupil_area;
+        let weight = match channel {
+            None => Colour { x: w, y: w, z: w },
+            Some(c) => {
+                let mut v = [0.0; 3];
+                v[c] = 3.0 * w;
+                Colour {
+                    x: v[0],
+                    y: v[1],
+                    z: v[2],
+                }
+            }
+        };
+        Some((out, weight))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+    use crate::sampler::Sampler;
+    use crate::sampler::Samplers;
+
+    // A stopped down 50mm biconvex singlet.
+    const SINGLET: &str = "
+        # radius thickness ior aperture abbe
+        0     2   0    8
+        51.5  5   1.5  20  60
+        -51.5 50  1    20";
+
+    #[test]
+    fn singlet_focuses() {
+        let elements = parse(SINGLET).unwrap();
+        assert_eq!(elements.len(), 3);
+        assert_eq!(elements[1].abbe, 60.0);
+        assert!(parse("1 2 3").is_err());
+
+        let fd = 2000.0;
+        let mut sampler = Samplers::new("independent", 1, 1).unwrap();
+        let mut achromat = elements.clone();
+        achromat[1].abbe = 0.0;
+        let lens = LensSystem::new(achromat, 36.0, 24.0, fd).unwrap();
+        let mut hits = 0;
+        for i in 0..256 {
+            sampler.start_sample(0, 0, i);
+            let Some((r, w)) = lens.sample(0.5, 0.5, &mut sampler) else {
+                continue;
+            };
+            hits += 1;
+            assert!(w.x == w.y && w.y == w.z && w.x > 0.0, "{w:?}");
+            // Rays from the film centre converge on the axis at the focus
+            // distance, up to spherical aberration.
+            let t = (-fd - r.orig.z) / r.dir.z;
+            let p = r.at(t);
+            assert!(p.x.hypot(p.y) < 2.0, "{p:?}");
+        }
+        assert!(hits > 128, "{hits}");
+
+        // Light from far off axis misses the film entirely.
+        let r = Ray {
+            orig: crate::vec::Vec {
+                x: 500.0,
+                y: 0.0,
+                z: -100.0,
+            },
+            dir: crate::vec::Vec {
+                x: -1.0,
+                y: 0.0,
+                z: 0.1,
+            },
+        };
+        assert!(lens.trace_from_scene(&r, None).is_none());
+
+        // A dispersive lens traces one channel per ray.
+        let lens = LensSystem::new(elements, 36.0, 24.0, fd).unwrap();
+        for i in 0..64 {
+            sampler.start_sample(0, 0, i);
+            if let Some((_, w)) = lens.sample(0.3, 0.6, &mut sampler) {
+                let lit = [w.x, w.y, w.z].iter().filter(|&&c| c > 0.0).count();
+                assert_eq!(lit, 1, "{w:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! A small path tracer, after Ray Tracing in One Weekend.
+//!
+//! Build a [`Scene`] in code or parse one with [`scene::parse`], pick the
+//! image size, sampling and integrator with [`Settings`], and render it to
+//! an [`Image`] of linear floating point colours with a [`Renderer`]:
+//!
+//! ```
+//! use ray_tracer_rust::{Renderer, Settings};
+//!
+//! let scene = ray_tracer_rust::scene::parse(
+//!     "sphere 0 0 -1 0.5 lambertian 0.7 0.3 0.3
+//!      sphere 0 -100.5 -1 100 lambertian 0.5 0.5 0.5",
+//! )
+//! .unwrap();
+//! let settings = Settings {
+//!     image_width: 16,
+//!     n_samples: 4,
+//!     ..Default::default()
+//! };
+//! let image = Renderer::new(&scene)
+//!     .settings(settings)
+//!     .on_progress(|p| eprint!("\rscan lines remaining: {}", p.lines_remaining))
+//!     .render()
+//!     .unwrap();
+//! assert_eq!((image.width, image.height), (16, 10));
+//! assert_eq!(image.data.len(), 16 * 10 * 3);
+//! ```
+//!
+//! [`render_scene`] does the same with no callbacks and returns the
+//! [`Framebuffer`], which also holds sample counts and filter weights.
+//!
+//! Shapes and materials beyond the built in ones implement [`Hittable`] or
+//! [`Material`] and are added with [`Hittables::custom`] and
+//! [`Materials::custom`].
+//!
+//! The modules below are public for the command line tool, benchmarks and
+//! tests; the re-exports here are the stable surface.
+
+pub mod animation;
+pub mod aov;
+pub mod bvh;
+pub mod camera;
+pub mod denoise;
+pub mod dielectric;
+pub mod filter;
+pub mod hittable;
+pub mod integrator;
+pub mod lambertian;
+pub mod lens;
+pub mod material;
+pub mod metal;
+pub mod ray;
+pub mod render;
+pub mod renderer;
+pub mod sampler;
+pub mod scene;
+pub mod settings;
+pub mod sphere;
+pub mod stats;
+pub mod util;
+pub mod vec;
+
+pub use bvh::Aabb;
+pub use camera::Camera;
+pub use dielectric::Dielectric;
+pub use hittable::HitRecord;
+pub use hittable::Hittable;
+pub use hittable::HittableList;
+pub use hittable::Hittables;
+pub use integrator::Integrator;
+pub use lambertian::Lambertian;
+pub use material::Lobe;
+pub use material::Material;
+pub use material::Materials;
+pub use metal::Metal;
+pub use ray::Ray;
+pub use render::render_scene;
+pub use render::Framebuffer;
+pub use render::Image;
+pub use render::Progress;
+pub use render::Region;
+pub use renderer::Frame;
+pub use renderer::RenderError;
+pub use renderer::Renderer;
+pub use sampler::Sampler;
+pub use sampler::Samplers;
+pub use scene::CameraDesc;
+pub use scene::Scene;
+pub use settings::Settings;
+pub use sphere::Sphere;
+pub use vec::Colour;
+pub use vec::Point;
+pub use vec::Vec;
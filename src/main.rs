@@ -1,214 +1,150 @@
-use std::fs::File;
-use std::io::prelude::*;
+use std::io::Write;
+use std::time::Instant;
+
+use ray_tracer_rust::animation;
+use ray_tracer_rust::aov;
+use ray_tracer_rust::render;
+use ray_tracer_rust::scene;
+use ray_tracer_rust::settings;
+use ray_tracer_rust::stats;
+use ray_tracer_rust::util::Rng;
+use ray_tracer_rust::Framebuffer;
+use ray_tracer_rust::RenderError;
+use ray_tracer_rust::Renderer;
 
-mod camera;
-mod dielectric;
-mod hittable;
-mod lambertian;
-mod material;
-mod metal;
-mod ray;
-mod sphere;
-mod util;
-mod vec;
-
-use dielectric::dielectric::Dielectric;
-use hittable::hittable::HitRecord;
-use hittable::hittable::Hittable;
-use hittable::hittable::HittableList;
-use hittable::hittable::Hittables;
-use lambertian::lambertian::Lambertian;
-use material::material::Material;
-use material::material::Materials;
-use metal::metal::Metal;
-use ray::ray::Ray;
-use sphere::sphere::Sphere;
-use util::util::rand_f32;
-use util::util::randmm_f32;
-use util::util::write_colour;
-use vec::vec::rand;
-use vec::vec::randmm;
-use vec::vec::Colour;
-use vec::vec::Point;
-use vec::vec::Vec;
-
-fn ray_colour(r: &Ray, world: &HittableList, depth: i32) -> Colour {
-    if depth <= 0 {
-        return Default::default();
-    }
-
-    let mut rec: HitRecord = Default::default();
-
-    if world.hit(r, 0.001, std::f32::MAX, &mut rec) {
-        let mut scattered: Ray = Default::default();
-        let mut att: Colour = Default::default();
-        if rec.mat.scatter(r, &rec, &mut att, &mut scattered) {
-            return att.mul(&[ray_colour(&scattered, world, depth - 1)]);
+fn main() -> std::io::Result<()> {
+    let settings = match settings::parse(std::env::args().skip(1)) {
+        Ok(s) => s,
+        Err(msg) => {
+            eprintln!("{msg}");
+            std::process::exit(2);
         }
-        return Default::default();
-    }
-
-    let unit = r.dir.unit();
-    let t = 0.5 * (unit.y + 1.0);
-    Colour {
-        x: 1.0,
-        y: 1.0,
-        z: 1.0,
-    }
-    .mulf(1.0 - t)
-    .add(&[Colour {
-        x: 0.5,
-        y: 0.7,
-        z: 1.0,
-    }
-    .mulf(t)])
-}
-
-fn random_scene() -> HittableList {
-    let mut world = HittableList {
-        objects: std::vec::Vec::new(),
     };
-
-    let mat_gnd = Materials::Lambertian(Lambertian {
-        albedo: Colour {
-            x: 0.5,
-            y: 0.5,
-            z: 0.5,
-        },
+    let aovs = settings.aov.as_ref().map(|names| {
+        aov::parse_list(names).unwrap_or_else(|msg| {
+            eprintln!("{msg}");
+            std::process::exit(2);
+        })
     });
-    world.objects.push(Hittables::Sphere(Sphere {
-        c: Point {
-            y: -1000.0,
-            ..Default::default()
-        },
-        r: 1000.0,
-        mat: mat_gnd,
-    }));
 
-    for i in -11..11 {
-        for j in -11..11 {
-            let choose = rand_f32();
-            let centre = Point {
-                x: i as f32 + 0.9 * rand_f32(),
-                y: 0.2,
-                z: j as f32 + 0.9 * rand_f32(),
-            };
-            if (centre.sub(&[Point {
-                x: 4.0,
-                y: 0.2,
-                ..Default::default()
-            }]))
-            .len()
-                > 0.9
-            {
-                let mat = if choose < 0.8 {
-                    let albedo = rand().mul(&[rand()]);
-                    Materials::Lambertian(Lambertian { albedo })
-                } else if choose < 0.96 {
-                    let albedo = randmm(0.5, 1.0);
-                    let fuzz = randmm_f32(0.0, 0.5);
-                    Materials::Metal(Metal { albedo, fuzz })
-                } else {
-                    Materials::Dielectric(Dielectric { ir: 1.5 })
-                };
-                world.objects.push(Hittables::Sphere(Sphere {
-                    c: centre,
-                    r: 0.2,
-                    mat,
-                }));
-            }
-        }
+    let mut report = stats::Report {
+        counters: Default::default(),
+        timings: Default::default(),
+    };
+    let start = Instant::now();
+    let mut scene = match &settings.scene {
+        Some(path) => scene::load(path),
+        None => Ok(Default::default()),
     }
-
-    let mut mat = Materials::Dielectric(Dielectric { ir: 1.5 });
-    world.objects.push(Hittables::Sphere(Sphere {
-        c: Point {
-            y: 1.0,
-            ..Default::default()
-        },
-        r: 1.0,
-        mat,
-    }));
-
-    mat = Materials::Lambertian(Lambertian {
-        albedo: Colour {
-            x: 0.4,
-            y: 0.2,
-            z: 0.1,
-        },
+    .unwrap_or_else(|msg| {
+        eprintln!("{msg}");
+        std::process::exit(2);
     });
-    world.objects.push(Hittables::Sphere(Sphere {
-        c: Point {
-            x: -4.0,
-            y: 1.0,
-            ..Default::default()
-        },
-        r: 1.0,
-        mat,
-    }));
-
-    mat = Materials::Metal(Metal {
-        albedo: Colour {
-            x: 0.7,
-            y: 0.6,
-            z: 0.5,
-        },
-        ..Default::default()
-    });
-    world.objects.push(Hittables::Sphere(Sphere {
-        c: Point {
-            x: 4.0,
-            y: 1.0,
-            ..Default::default()
-        },
-        r: 1.0,
-        mat,
-    }));
-
-    return world;
-}
-
-fn main() -> std::io::Result<()> {
-    let aspect_ratio = 3.0 / 2.0;
-    let image_width = 400;
-    let image_height = (image_width as f32 / aspect_ratio) as i32;
-    let n_samples = 100;
-    let max_depth = 50;
-
-    let world = random_scene();
-
-    let lf = Point {
-        x: 13.0,
-        y: 2.0,
-        z: 3.0,
+    settings
+        .apply_camera(&mut scene.camera)
+        .unwrap_or_else(|msg| {
+            eprintln!("{msg}");
+            std::process::exit(2);
+        });
+    if scene.world.objects.is_empty() {
+        scene.world = scene::random(&mut Rng::new(settings.seed));
+    }
+    report.timings.scene += start.elapsed();
+
+    // Animated scenes render each frame to its own numbered files.
+    let range = scene.animation.frame_range();
+    let frames: std::vec::Vec<Option<i32>> = match (
+        settings.first_frame.or(range.map(|r| r.0)),
+        settings.last_frame.or(range.map(|r| r.1)),
+    ) {
+        (Some(a), Some(b)) => (a..=b).map(Some).collect(),
+        (Some(a), None) | (None, Some(a)) => vec![Some(a)],
+        (None, None) => vec![None],
     };
-    let la: Point = Default::default();
-    let vup = Vec {
-        y: 1.0,
-        ..Default::default()
+    let path = |path: &str, frame: Option<i32>| match frame {
+        Some(f) => animation::frame_path(path, f),
+        None => path.to_string(),
     };
-    let fd = 10.0;
-    let ap = 0.1;
-    let cam = camera::camera::init(&lf, &la, &vup, 20.0, aspect_ratio, ap, fd);
 
-    let mut f = File::create("img.ppm")?;
-    f.write_all(format!("P3\n{image_width} {image_height}\n255\n").as_bytes())?;
+    // Region renders black out or crop away everything outside the region.
+    let in_region = |fb: &Framebuffer, r: &render::Region| fb.region(r, !settings.crop);
+    let write = |fb: &Framebuffer, output: &str| match &settings.region {
+        Some(r) => in_region(fb, r).write_ppm(output),
+        None => fb.write_ppm(output),
+    };
 
-    for j in (0..image_height).rev() {
-        print!("\rscan lines remaining: {j}  ");
-        std::io::stdout().flush().unwrap();
-        for i in 0..image_width {
-            let mut c: Colour = Default::default();
-            for _ in 0..n_samples {
-                let u = (i as f32 + rand_f32()) / (image_width - 1) as f32;
-                let v = (j as f32 + rand_f32()) / (image_height - 1) as f32;
-                let r = cam.get_ray(u, v);
-                c = c.add(&[ray_colour(&r, &world, max_depth)]);
+    for frame in frames {
+        if let Some(f) = frame {
+            println!("frame {f}");
+        }
+        let output = path(&settings.output, frame);
+        let checkpoint = settings.checkpoint.as_ref().map(|c| path(c, frame));
+        let mut last_checkpoint = Instant::now();
+        let mut renderer = Renderer::new(&scene)
+            .settings(settings.clone())
+            .aovs(aovs.as_deref().unwrap_or_default())
+            .on_progress(|p| {
+                print!(
+                    "\rpass {}, scan lines remaining: {}  ",
+                    p.pass, p.lines_remaining
+                );
+                let _ = std::io::stdout().flush();
+            })
+            .on_pass(|fb, info| {
+                write(fb, &output)?;
+                if let Some(checkpoint) = &checkpoint {
+                    if last_checkpoint.elapsed().as_secs_f32() >= settings.checkpoint_interval {
+                        fb.save_checkpoint(checkpoint, info)?;
+                        last_checkpoint = Instant::now();
+                    }
+                }
+                Ok(())
+            })
+            .on_warning(|w| eprintln!("{w}"));
+        if let Some(f) = frame {
+            renderer = renderer.frame(f as f32);
+        }
+        if let Some(resume) = &settings.resume {
+            let (fb, saved) = Framebuffer::load_checkpoint(&path(resume, frame))?;
+            renderer = renderer.resume(fb, saved);
+        }
+        let result = match renderer.render_frame() {
+            Ok(result) => result,
+            Err(RenderError::Invalid(msg)) => {
+                eprintln!("{msg}");
+                std::process::exit(2);
             }
-            write_colour(&mut f, &c, n_samples)?;
+            Err(RenderError::Io(msg)) => return Err(std::io::Error::other(msg)),
+            Err(e) => return Err(std::io::Error::other(e)),
+        };
+        println!();
+        report.counters += result.counters;
+        report.timings += result.timings;
+
+        let start = Instant::now();
+        let fb = &result.framebuffer;
+        write(result.denoised.as_ref().unwrap_or(fb), &output)?;
+        if let Some(aovs) = &result.aovs {
+            aovs.write(&output, settings.region.as_ref(), settings.crop)?;
+        }
+        if let Some(checkpoint) = &checkpoint {
+            fb.save_checkpoint(checkpoint, &result.checkpoint)?;
         }
+        if let Some(spp_map) = &settings.spp_map {
+            let spp_map = path(spp_map, frame);
+            match &settings.region {
+                Some(r) => in_region(fb, r).write_spp_map(&spp_map, settings.n_samples)?,
+                None => fb.write_spp_map(&spp_map, settings.n_samples)?,
+            }
+        }
+        report.timings.output += start.elapsed();
+    }
+    println!("done");
+
+    println!("{}", report.summary());
+    if let Some(path) = &settings.stats_json {
+        std::fs::write(path, report.json())?;
     }
-    println!("\ndone");
 
     Ok(())
 }
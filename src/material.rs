@@ -1,44 +1,95 @@
-pub mod material {
-    use crate::dielectric;
-    use crate::hittable;
-    use crate::lambertian;
-    use crate::metal;
-    use crate::ray;
-    use crate::vec;
-
-    pub trait Material {
-        fn scatter(
-            &self,
-            r: &ray::ray::Ray,
-            rec: &hittable::hittable::HitRecord,
-            att: &mut vec::vec::Colour,
-            scattered: &mut ray::ray::Ray,
-        ) -> bool;
+use std::sync::Arc;
+
+use crate::dielectric;
+use crate::hittable;
+use crate::lambertian;
+use crate::metal;
+use crate::ray;
+use crate::sampler;
+use crate::vec;
+
+// Kind of scattering event, for splitting light into passes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Lobe {
+    Diffuse,
+    // Reflection off a mirror-like or glossy surface.
+    Specular,
+    // Refraction into or out of a transparent surface.
+    Transmission,
+}
+
+pub trait Material {
+    // Returns the lobe that was sampled, or None if the ray is absorbed.
+    fn scatter(
+        &self,
+        r: &ray::Ray,
+        rec: &hittable::HitRecord,
+        att: &mut vec::Colour,
+        scattered: &mut ray::Ray,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> Option<Lobe>;
+
+    // Overall surface colour, for albedo outputs and denoising.
+    fn albedo(&self) -> vec::Colour;
+}
+
+#[derive(Clone, Default)]
+pub enum Materials {
+    #[default]
+    MaterialNone,
+    Dielectric(dielectric::Dielectric),
+    Lambertian(lambertian::Lambertian),
+    Metal(metal::Metal),
+    // A material defined outside this crate, called through a vtable
+    // rather than matched on like the built in ones.
+    Custom(Arc<dyn Material + Send + Sync>),
+}
+
+impl Materials {
+    pub fn custom(mat: impl Material + Send + Sync + 'static) -> Materials {
+        Materials::Custom(Arc::new(mat))
     }
+}
 
-    #[derive(Copy, Clone, Default)]
-    pub enum Materials {
-        #[default]
-        MaterialNone,
-        Dielectric(dielectric::dielectric::Dielectric),
-        Lambertian(lambertian::lambertian::Lambertian),
-        Metal(metal::metal::Metal),
+// Custom materials are equal only to themselves.
+impl PartialEq for Materials {
+    fn eq(&self, other: &Materials) -> bool {
+        match (self, other) {
+            (Materials::MaterialNone, Materials::MaterialNone) => true,
+            (Materials::Dielectric(a), Materials::Dielectric(b)) => a == b,
+            (Materials::Lambertian(a), Materials::Lambertian(b)) => a == b,
+            (Materials::Metal(a), Materials::Metal(b)) => a == b,
+            (Materials::Custom(a), Materials::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Material for Materials {
+    fn scatter(
+        &self,
+        r: &ray::Ray,
+        rec: &hittable::HitRecord,
+        att: &mut vec::Colour,
+        scattered: &mut ray::Ray,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> Option<Lobe> {
+        match self {
+            Materials::MaterialNone => None,
+            Materials::Dielectric(mat) => mat.scatter(r, rec, att, scattered, sampler),
+            Materials::Lambertian(mat) => mat.scatter(r, rec, att, scattered, sampler),
+            Materials::Metal(mat) => mat.scatter(r, rec, att, scattered, sampler),
+            Materials::Custom(mat) => mat.scatter(r, rec, att, scattered, sampler),
+        }
     }
 
-    impl Material for Materials {
-        fn scatter(
-            &self,
-            r: &ray::ray::Ray,
-            rec: &hittable::hittable::HitRecord,
-            att: &mut vec::vec::Colour,
-            scattered: &mut ray::ray::Ray,
-        ) -> bool {
-            match self {
-                Materials::MaterialNone => false,
-                Materials::Dielectric(mat) => mat.scatter(r, rec, att, scattered),
-                Materials::Lambertian(mat) => mat.scatter(r, rec, att, scattered),
-                Materials::Metal(mat) => mat.scatter(r, rec, att, scattered),
-            }
+    fn albedo(&self) -> vec::Colour {
+        match self {
+            Materials::MaterialNone => Default::default(),
+            Materials::Dielectric(mat) => mat.albedo(),
+            Materials::Lambertian(mat) => mat.albedo(),
+            Materials::Metal(mat) => mat.albedo(),
+            Materials::Custom(mat) => mat.albedo(),
         }
     }
 }
@@ -1,30 +1,34 @@
-pub mod metal {
-    use crate::hittable;
-    use crate::material;
-    use crate::ray;
-    use crate::vec;
+use crate::hittable;
+use crate::material;
+use crate::ray;
+use crate::sampler;
+use crate::vec;
 
-    #[derive(Copy, Clone, Debug, Default)]
-    pub struct Metal {
-        pub albedo: vec::vec::Colour,
-        pub fuzz: f32,
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Metal {
+    pub albedo: vec::Colour,
+    pub fuzz: f32,
+}
+
+impl material::Material for Metal {
+    fn scatter(
+        &self,
+        r: &ray::Ray,
+        rec: &hittable::HitRecord,
+        att: &mut vec::Colour,
+        scattered: &mut ray::Ray,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> Option<material::Lobe> {
+        let refl = r.dir.unit().reflect(&rec.n);
+        *scattered = ray::Ray {
+            orig: rec.p,
+            dir: refl.add(&[vec::rand_unit_sphere(sampler).mulf(self.fuzz)]),
+        };
+        *att = self.albedo;
+        (scattered.dir.dot(&rec.n) > 0.0).then_some(material::Lobe::Specular)
     }
 
-    impl material::material::Material for Metal {
-        fn scatter(
-            &self,
-            r: &ray::ray::Ray,
-            rec: &hittable::hittable::HitRecord,
-            att: &mut vec::vec::Colour,
-            scattered: &mut ray::ray::Ray,
-        ) -> bool {
-            let refl = r.dir.unit().reflect(&rec.n);
-            *scattered = ray::ray::Ray {
-                orig: rec.p,
-                dir: refl.add(&[vec::vec::rand_unit_sphere().mulf(self.fuzz)]),
-            };
-            *att = self.albedo;
-            scattered.dir.dot(&rec.n) > 0.0
-        }
+    fn albedo(&self) -> vec::Colour {
+        self.albedo
     }
 }
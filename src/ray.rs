@@ -1,16 +1,14 @@
-pub mod ray {
-    use crate::vec;
+use crate::vec;
 
-    #[derive(Default)]
-    pub struct Ray {
-        pub orig: vec::vec::Point,
-        pub dir: vec::vec::Vec,
-    }
+#[derive(Copy, Clone, Default)]
+pub struct Ray {
+    pub orig: vec::Point,
+    pub dir: vec::Vec,
+}
 
-    impl Ray {
-        pub fn at(&self, t: f32) -> vec::vec::Point {
-            self.orig.add(&[self.dir.mulf(t)])
-        }
+impl Ray {
+    pub fn at(&self, t: f32) -> vec::Point {
+        self.orig.add(&[self.dir.mulf(t)])
     }
 }
 
@@ -20,13 +18,13 @@ mod tests {
     use crate::vec;
     #[test]
     fn ray_at() {
-        let r = ray::Ray {
-            orig: vec::vec::Vec {
+        let r = Ray {
+            orig: vec::Vec {
                 x: 1.0,
                 y: 2.0,
                 z: 3.0,
             },
-            dir: vec::vec::Vec {
+            dir: vec::Vec {
                 x: 4.0,
                 y: 5.0,
                 z: 6.0,
@@ -34,7 +32,7 @@ mod tests {
         };
         assert_eq!(
             r.at(2.0),
-            vec::vec::Vec {
+            vec::Vec {
                 x: 9.0,
                 y: 12.0,
                 z: 15.0,
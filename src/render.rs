@@ -0,0 +1,1060 @@
+use crate::aov;
+use crate::aov::Aov;
+use crate::camera;
+use crate::filter::Filter;
+use crate::filter::Filters;
+use crate::hittable::HitRecord;
+use crate::hittable::Hittable;
+use crate::hittable::HittableList;
+use crate::integrator;
+use crate::integrator::Integrator;
+use crate::material::Lobe;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::renderer::RenderError;
+use crate::renderer::Renderer;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::settings::Settings;
+use crate::stats;
+use crate::util;
+use crate::vec::Colour;
+use std::fs::File;
+use std::io::prelude::*;
+
+pub fn background(r: &Ray) -> Colour {
+    let unit = r.dir.unit();
+    let t = 0.5 * (unit.y + 1.0);
+    Colour {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+    }
+    .mulf(1.0 - t)
+    .add(&[Colour {
+        x: 0.5,
+        y: 0.7,
+        z: 1.0,
+    }
+    .mulf(t)])
+}
+
+// Traces a path from the camera, returning the light it carries back, the
+// light pass it belongs to and what the camera ray hit.
+pub fn ray_colour(
+    r: &Ray,
+    world: &HittableList,
+    max_depth: i32,
+    rr_depth: i32,
+    sampler: &mut dyn Sampler,
+) -> (Colour, Aov, Option<HitRecord>) {
+    let (c, first, bounces, hit) = trace(r, world, max_depth, rr_depth, sampler);
+    stats::add(&stats::PATHS, 1);
+    stats::add(&stats::PATH_BOUNCES, bounces as u64);
+    (c, aov::light_pass(first, bounces), hit)
+}
+
+// The path tracer: returns the light carried back along r, the lobe of
+// the first bounce, the number of bounces before the path ended and the
+// first hit, if any.
+pub fn trace(
+    r: &Ray,
+    world: &HittableList,
+    max_depth: i32,
+    rr_depth: i32,
+    sampler: &mut dyn Sampler,
+) -> (Colour, Option<Lobe>, i32, Option<HitRecord>) {
+    let mut ray = *r;
+    let mut throughput = Colour {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+    };
+    let mut first = None;
+    let mut first_hit = None;
+
+    for depth in 0..max_depth {
+        if depth > 0 {
+            stats::add(&stats::BOUNCE_RAYS, 1);
+        }
+        let mut rec: HitRecord = Default::default();
+        if !world.hit(&ray, 0.001, f32::MAX, &mut rec) {
+            return (throughput.mul(&[background(&ray)]), first, depth, first_hit);
+        }
+
+        let mut scattered: Ray = Default::default();
+        let mut att: Colour = Default::default();
+        let lobe = rec
+            .mat
+            .scatter(&ray, &rec, &mut att, &mut scattered, sampler);
+        if depth == 0 {
+            first_hit = Some(rec);
+        }
+        if lobe.is_none() {
+            return (Default::default(), first, depth, first_hit);
+        }
+        first = first.or(lobe);
+        throughput = throughput.mul(&[att]);
+
+        // Russian roulette: past rr_depth, terminate with probability 1 - q and
+        // boost survivors by 1 / q so the estimate stays unbiased.
+        if depth >= rr_depth {
+            let q = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+            if sampler.get_1d() >= q {
+                return (Default::default(), first, depth + 1, first_hit);
+            }
+            throughput = throughput.divf(q);
+        }
+
+        ray = scattered;
+    }
+
+    (Default::default(), first, max_depth, first_hit)
+}
+
+pub fn luminance(c: &Colour) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+// Fraction of the absolute filter weight a pixel needs left after negative
+// lobes cancel, below which its filtered colour is not trusted.
+const MIN_WEIGHT: f32 = 0.5;
+
+#[derive(Copy, Clone, Default)]
+pub struct Pixel {
+    // Filter weighted sum of the samples splatted onto this pixel, and the
+    // sum of their weights.
+    pub sum: Colour,
+    pub weight: f32,
+    // Sum of the weights' magnitudes, which negative lobes don't cancel.
+    abs_weight: f32,
+    // Number of samples taken inside this pixel, and their plain sum.
+    pub n: u32,
+    own: Colour,
+    // Running mean and sum of squared deviations of the sample luminance
+    // (Welford), used to estimate the error of the pixel.
+    mean: f32,
+    m2: f32,
+}
+
+impl Pixel {
+    // Filters with negative lobes can leave a pixel with little or even
+    // negative weight at low sample counts, and dividing by it blows the
+    // colour up. Such pixels fall back to the box filtered average of their
+    // own samples. Whether they do depends only on how much of the weight
+    // cancelled, not on how many samples the pixel took.
+    pub fn colour(&self) -> Colour {
+        if self.n > 0 && self.weight < MIN_WEIGHT * self.abs_weight {
+            self.own.divf(self.n as f32)
+        } else if self.weight > 0.0 {
+            self.sum.divf(self.weight)
+        } else {
+            Default::default()
+        }
+    }
+
+    // Replaces the filtered colour with c, as if every sample had weight
+    // one, keeping the sample statistics.
+    pub fn set_colour(&mut self, c: &Colour) {
+        self.weight = self.n.max(1) as f32;
+        self.abs_weight = self.weight;
+        self.sum = c.mulf(self.weight);
+    }
+
+    pub fn add_sample(&mut self, c: &Colour) {
+        self.n += 1;
+        self.own = self.own.add(&[*c]);
+        let l = luminance(c);
+        let d = l - self.mean;
+        self.mean += d / self.n as f32;
+        self.m2 += d * (l - self.mean);
+    }
+
+    // Estimated variance of the mean sample luminance, unknown (infinite)
+    // with fewer than two samples.
+    pub fn variance(&self) -> f32 {
+        if self.n < 2 {
+            return f32::MAX;
+        }
+        self.m2 / (self.n - 1) as f32 / self.n as f32
+    }
+
+    // Standard error of the mean luminance relative to the mean itself. Dark
+    // pixels are measured against a floor of 0.1 so they can still converge.
+    pub fn rel_err(&self) -> f32 {
+        if self.n < 2 {
+            return f32::MAX;
+        }
+        self.variance().sqrt() / self.mean.max(0.1)
+    }
+}
+
+// A rectangle of pixels, x0..x1 by y0..y1 with rows counted down from the
+// top of the image, written as x0,y0,x1,y1.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Region {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+}
+
+impl std::str::FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Region, String> {
+        let v: std::vec::Vec<i32> = s
+            .split(',')
+            .map(|v| v.trim().parse().map_err(|_| format!("invalid number: {v}")))
+            .collect::<Result<_, _>>()?;
+        match v[..] {
+            [x0, y0, x1, y1] if x0 < x1 && y0 < y1 => Ok(Region { x0, y0, x1, y1 }),
+            [_, _, _, _] => Err(String::from("region is empty")),
+            _ => Err(String::from("expected x0,y0,x1,y1")),
+        }
+    }
+}
+
+impl Region {
+    // Whether pixel (i, j), with j counted up from the bottom row, lies
+    // within the region grown by margin pixels on every side.
+    fn contains(&self, i: i32, j: i32, height: i32, margin: i32) -> bool {
+        let y = height - 1 - j;
+        i >= self.x0 - margin
+            && i < self.x1 + margin
+            && y >= self.y0 - margin
+            && y < self.y1 + margin
+    }
+}
+
+// How far a render has got: the pass under way and the scan lines it still
+// has to visit.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Progress {
+    pub pass: u32,
+    pub lines_remaining: i32,
+    pub height: i32,
+}
+
+// Linear RGB colours as three floats per pixel, rows from the top of the
+// image down.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Image {
+    pub width: i32,
+    pub height: i32,
+    pub data: std::vec::Vec<f32>,
+}
+
+impl Image {
+    // Colour of pixel (x, y), with y counted down from the top.
+    pub fn pixel(&self, x: i32, y: i32) -> Colour {
+        let idx = 3 * (y * self.width + x) as usize;
+        Colour {
+            x: self.data[idx],
+            y: self.data[idx + 1],
+            z: self.data[idx + 2],
+        }
+    }
+}
+
+const CHECKPOINT_MAGIC: &[u8] = b"RTCKPT5\n";
+// Sum, weight, absolute weight, count, own sum, mean and m2.
+const PIXEL_BYTES: usize = 44;
+
+// What produced a checkpoint's samples. Resuming with a different sampler,
+// seed or filter would mix samples that don't belong together.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CheckpointInfo {
+    pub seed: u64,
+    pub sampler: String,
+    pub filter: String,
+    pub filter_radius: f32,
+    pub n_samples: u32,
+    // scene::fingerprint of the posed camera and objects.
+    pub scene: u64,
+    pub integrator: String,
+    pub max_depth: i32,
+    pub rr_depth: i32,
+    pub ao_distance: f32,
+}
+
+impl CheckpointInfo {
+    // Whether a render described by self can continue a checkpoint described
+    // by saved. Raising the sample count is the point of resuming, but the
+    // stratified sampler lays its strata out for a fixed count.
+    pub fn can_resume(&self, saved: &CheckpointInfo) -> Result<(), String> {
+        let differs = |what: &str, now: &dyn std::fmt::Display, then: &dyn std::fmt::Display| {
+            Err(format!(
+                "the checkpoint was rendered with {what} {then}, not {now}"
+            ))
+        };
+        if self.sampler != saved.sampler {
+            return differs("sampler", &self.sampler, &saved.sampler);
+        }
+        if self.seed != saved.seed {
+            return differs("seed", &self.seed, &saved.seed);
+        }
+        if self.filter != saved.filter || self.filter_radius != saved.filter_radius {
+            return differs(
+                "filter",
+                &format!("{} {}", self.filter, self.filter_radius),
+                &format!("{} {}", saved.filter, saved.filter_radius),
+            );
+        }
+        if self.scene != saved.scene {
+            return Err(String::from(
+                "the checkpoint was rendered from a different scene or camera",
+            ));
+        }
+        if self.integrator != saved.integrator {
+            return differs("integrator", &self.integrator, &saved.integrator);
+        }
+        if self.max_depth != saved.max_depth {
+            return differs("max depth", &self.max_depth, &saved.max_depth);
+        }
+        if self.rr_depth != saved.rr_depth {
+            return differs("rr depth", &self.rr_depth, &saved.rr_depth);
+        }
+        if self.integrator == "ao" && self.ao_distance != saved.ao_distance {
+            return differs("ao distance", &self.ao_distance, &saved.ao_distance);
+        }
+        if self.sampler == "stratified" && self.n_samples != saved.n_samples {
+            return differs("samples", &self.n_samples, &saved.n_samples);
+        }
+        Ok(())
+    }
+}
+
+pub struct Framebuffer {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: std::vec::Vec<Pixel>,
+}
+
+impl Framebuffer {
+    pub fn new(width: i32, height: i32) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Default::default(); (width * height) as usize],
+        }
+    }
+
+    pub fn at(&self, i: i32, j: i32) -> &Pixel {
+        &self.pixels[(j * self.width + i) as usize]
+    }
+
+    // Adds a sample taken at film position (x, y), in pixels, to every pixel
+    // whose centre lies within the filter radius.
+    pub fn splat(&mut self, x: f32, y: f32, c: &Colour, filter: &Filters) {
+        let r = filter.radius();
+        let i0 = ((x - 0.5 - r).floor() as i32 + 1).max(0);
+        let i1 = ((x - 0.5 + r).floor() as i32).min(self.width - 1);
+        let j0 = ((y - 0.5 - r).floor() as i32 + 1).max(0);
+        let j1 = ((y - 0.5 + r).floor() as i32).min(self.height - 1);
+        for j in j0..=j1 {
+            for i in i0..=i1 {
+                let w = filter.eval(i as f32 + 0.5 - x, j as f32 + 0.5 - y);
+                if w != 0.0 {
+                    let px = &mut self.pixels[(j * self.width + i) as usize];
+                    px.sum = px.sum.add(&[c.mulf(w)]);
+                    px.weight += w;
+                    px.abs_weight += w.abs();
+                }
+            }
+        }
+    }
+
+    // Copies the pixels inside region, either into an image of just the
+    // region or, with keep_size, into a black image of the same size.
+    pub fn region(&self, region: &Region, keep_size: bool) -> Framebuffer {
+        let (w, h) = (region.x1 - region.x0, region.y1 - region.y0);
+        let mut out = if keep_size {
+            Framebuffer::new(self.width, self.height)
+        } else {
+            Framebuffer::new(w, h)
+        };
+        for y in region.y0.max(0)..region.y1.min(self.height) {
+            for x in region.x0.max(0)..region.x1.min(self.width) {
+                let (i, j) = if keep_size {
+                    (x, self.height - 1 - y)
+                } else {
+                    (x - region.x0, h - 1 - (y - region.y0))
+                };
+                out.pixels[(j * out.width + i) as usize] = *self.at(x, self.height - 1 - y);
+            }
+        }
+        out
+    }
+
+    pub fn image(&self) -> Image {
+        let mut data = std::vec::Vec::with_capacity(self.pixels.len() * 3);
+        for j in (0..self.height).rev() {
+            for i in 0..self.width {
+                let c = self.at(i, j).colour();
+                data.extend_from_slice(&[c.x, c.y, c.z]);
+            }
+        }
+        Image {
+            width: self.width,
+            height: self.height,
+            data,
+        }
+    }
+
+    pub fn write_ppm(&self, path: &str) -> std::io::Result<()> {
+        let mut f = File::create(path)?;
+        f.write_all(format!("P3\n{} {}\n255\n", self.width, self.height).as_bytes())?;
+        for j in (0..self.height).rev() {
+            for i in 0..self.width {
+                util::write_colour(&mut f, &self.at(i, j).colour(), 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Portable float map with 1 (greyscale) or 3 channels, bottom row first.
+    pub fn write_pfm(&self, path: &str, channels: usize) -> std::io::Result<()> {
+        let magic = if channels == 1 { "Pf" } else { "PF" };
+        let mut buf = format!("{magic}\n{} {}\n-1.0\n", self.width, self.height).into_bytes();
+        for px in &self.pixels {
+            let c = px.colour();
+            for v in &[c.x, c.y, c.z][..channels] {
+                buf.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        std::fs::write(path, buf)
+    }
+
+    // Checkpoints hold the raw float accumulation, so a render can be resumed
+    // or extended with more samples later, along with what produced it. The
+    // file is written next to its final name and renamed, so an interrupted
+    // write never clobbers the last good checkpoint.
+    pub fn save_checkpoint(&self, path: &str, info: &CheckpointInfo) -> std::io::Result<()> {
+        let mut buf = std::vec::Vec::with_capacity(64 + self.pixels.len() * PIXEL_BYTES);
+        buf.extend_from_slice(CHECKPOINT_MAGIC);
+        buf.extend_from_slice(&self.width.to_le_bytes());
+        buf.extend_from_slice(&self.height.to_le_bytes());
+        buf.extend_from_slice(&info.seed.to_le_bytes());
+        buf.extend_from_slice(&info.n_samples.to_le_bytes());
+        buf.extend_from_slice(&info.filter_radius.to_le_bytes());
+        buf.extend_from_slice(&info.scene.to_le_bytes());
+        buf.extend_from_slice(&info.max_depth.to_le_bytes());
+        buf.extend_from_slice(&info.rr_depth.to_le_bytes());
+        buf.extend_from_slice(&info.ao_distance.to_le_bytes());
+        for name in [&info.sampler, &info.filter, &info.integrator] {
+            buf.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            buf.extend_from_slice(name.as_bytes());
+        }
+        for px in &self.pixels {
+            for v in [px.sum.x, px.sum.y, px.sum.z, px.weight, px.abs_weight] {
+                buf.extend_from_slice(&v.to_le_bytes());
+            }
+            buf.extend_from_slice(&px.n.to_le_bytes());
+            for v in [px.own.x, px.own.y, px.own.z] {
+                buf.extend_from_slice(&v.to_le_bytes());
+            }
+            buf.extend_from_slice(&px.mean.to_le_bytes());
+            buf.extend_from_slice(&px.m2.to_le_bytes());
+        }
+        let tmp = format!("{path}.tmp");
+        std::fs::write(&tmp, buf)?;
+        std::fs::rename(tmp, path)
+    }
+
+    pub fn load_checkpoint(path: &str) -> std::io::Result<(Framebuffer, CheckpointInfo)> {
+        let buf = std::fs::read(path)?;
+        let invalid = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{path} is not a valid checkpoint"),
+            )
+        };
+        if !buf.starts_with(CHECKPOINT_MAGIC) {
+            return Err(invalid());
+        }
+        // Reads the next n bytes of the header, or None past the end.
+        fn take<const N: usize>(buf: &[u8], pos: &mut usize) -> Option<[u8; N]> {
+            let bytes = buf.get(*pos..*pos + N)?;
+            *pos += N;
+            bytes.try_into().ok()
+        }
+        fn name(buf: &[u8], pos: &mut usize) -> Option<String> {
+            let len = u32::from_le_bytes(take(buf, pos)?) as usize;
+            let bytes = buf.get(*pos..pos.checked_add(len)?)?;
+            *pos += len;
+            String::from_utf8(bytes.to_vec()).ok()
+        }
+        let mut pos = CHECKPOINT_MAGIC.len();
+        let width = i32::from_le_bytes(take(&buf, &mut pos).ok_or_else(invalid)?);
+        let height = i32::from_le_bytes(take(&buf, &mut pos).ok_or_else(invalid)?);
+        let seed = u64::from_le_bytes(take(&buf, &mut pos).ok_or_else(invalid)?);
+        let n_samples = u32::from_le_bytes(take(&buf, &mut pos).ok_or_else(invalid)?);
+        let filter_radius = f32::from_le_bytes(take(&buf, &mut pos).ok_or_else(invalid)?);
+        let scene = u64::from_le_bytes(take(&buf, &mut pos).ok_or_else(invalid)?);
+        let max_depth = i32::from_le_bytes(take(&buf, &mut pos).ok_or_else(invalid)?);
+        let rr_depth = i32::from_le_bytes(take(&buf, &mut pos).ok_or_else(invalid)?);
+        let ao_distance = f32::from_le_bytes(take(&buf, &mut pos).ok_or_else(invalid)?);
+        let sampler = name(&buf, &mut pos).ok_or_else(invalid)?;
+        let filter = name(&buf, &mut pos).ok_or_else(invalid)?;
+        let integrator = name(&buf, &mut pos).ok_or_else(invalid)?;
+        let info = CheckpointInfo {
+            seed,
+            sampler,
+            filter,
+            filter_radius,
+            n_samples,
+            scene,
+            integrator,
+            max_depth,
+            rr_depth,
+            ao_distance,
+        };
+
+        // The size comes from the file, so check it accounts for the rest of
+        // the file before allocating anything.
+        let pixels = (width > 0 && height > 0)
+            .then(|| (width as usize).checked_mul(height as usize))
+            .flatten()
+            .filter(|&n| i32::try_from(n).is_ok());
+        let expected = pixels.and_then(|n| n.checked_mul(PIXEL_BYTES)?.checked_add(pos));
+        if expected != Some(buf.len()) {
+            return Err(invalid());
+        }
+        let mut words = buf[pos..].chunks_exact(4).map(|w| [w[0], w[1], w[2], w[3]]);
+        let mut next = || words.next().unwrap();
+        let mut fb = Framebuffer::new(width, height);
+        for px in &mut fb.pixels {
+            px.sum.x = f32::from_le_bytes(next());
+            px.sum.y = f32::from_le_bytes(next());
+            px.sum.z = f32::from_le_bytes(next());
+            px.weight = f32::from_le_bytes(next());
+            px.abs_weight = f32::from_le_bytes(next());
+            px.n = u32::from_le_bytes(next());
+            px.own.x = f32::from_le_bytes(next());
+            px.own.y = f32::from_le_bytes(next());
+            px.own.z = f32::from_le_bytes(next());
+            px.mean = f32::from_le_bytes(next());
+            px.m2 = f32::from_le_bytes(next());
+        }
+        Ok((fb, info))
+    }
+
+    // Greyscale map of samples per pixel, scaled so that max_spp is white.
+    pub fn write_spp_map(&self, path: &str, max_spp: i32) -> std::io::Result<()> {
+        let mut f = File::create(path)?;
+        f.write_all(format!("P2\n{} {}\n255\n", self.width, self.height).as_bytes())?;
+        for j in (0..self.height).rev() {
+            for i in 0..self.width {
+                let v = (255 * self.at(i, j).n as i32 / max_spp.max(1)).min(255);
+                f.write_all(format!("{v}\n").as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Renders in passes, accumulating into fb, which may already hold samples
+// from a resumed checkpoint. Each pass gives every unfinished pixel another
+// batch of samples: pass_samples if set, min_samples when adaptive, or
+// everything up to n_samples at once otherwise. Adaptive sampling skips
+// pixels whose relative error is already below the threshold. With a
+// region, only pixels whose filter footprint reaches into it are sampled.
+// AOVs, if given, record the first hit of every camera ray and split the
+// samples into light passes. on_progress is called before every scan line
+// and cancels the render, with an Interrupted error, by returning false.
+// on_pass is called with the framebuffer after every pass.
+#[allow(clippy::too_many_arguments)]
+pub fn render(
+    settings: &Settings,
+    world: &HittableList,
+    cam: &camera::Camera,
+    sampler: &mut dyn Sampler,
+    filter: &Filters,
+    fb: &mut Framebuffer,
+    mut aovs: Option<&mut aov::Aovs>,
+    mut on_progress: impl FnMut(Progress) -> bool,
+    mut on_pass: impl FnMut(&Framebuffer) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let width = fb.width;
+    let height = fb.height;
+    let max_spp = settings.n_samples.max(1) as u32;
+    let batch = if settings.pass_samples > 0 {
+        settings.pass_samples as u32
+    } else if settings.adaptive_threshold > 0.0 {
+        settings.min_samples.max(2) as u32
+    } else {
+        max_spp
+    };
+    // Samples splat up to the filter radius away, so a margin of pixels
+    // around the region contributes to it.
+    let margin = (filter.radius() - 0.5).ceil().max(0.0) as i32;
+    let needs_samples = |px: &Pixel, i: i32, j: i32| {
+        settings
+            .region
+            .is_none_or(|r| r.contains(i, j, height, margin))
+            && px.n < max_spp
+            && (px.n == 0 || px.rel_err() >= settings.adaptive_threshold)
+    };
+
+    for pass in 1.. {
+        let unfinished = fb
+            .pixels
+            .iter()
+            .enumerate()
+            .any(|(idx, px)| needs_samples(px, idx as i32 % width, idx as i32 / width));
+        if !unfinished {
+            break;
+        }
+        for j in (0..height).rev() {
+            let progress = Progress {
+                pass,
+                lines_remaining: j,
+                height,
+            };
+            if !on_progress(progress) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Interrupted,
+                    "render cancelled",
+                ));
+            }
+            for i in 0..width {
+                let idx = (j * width + i) as usize;
+                if !needs_samples(&fb.pixels[idx], i, j) {
+                    continue;
+                }
+                let n = fb.pixels[idx].n;
+                for s in n..(n + batch).min(max_spp) {
+                    stats::add(&stats::SAMPLES, 1);
+                    sampler.start_sample(i, j, s);
+                    let (du, dv) = sampler.get_2d();
+                    let x = i as f32 + du;
+                    let y = j as f32 + dv;
+                    let (s, t) = (x / (width - 1) as f32, y / (height - 1) as f32);
+                    let (c, pass) = match cam.generate_ray(s, t, sampler) {
+                        Some((r, weight)) => {
+                            stats::add(&stats::CAMERA_RAYS, 1);
+                            if settings.integrator != Integrator::Path {
+                                // Debug colours are shown as they are,
+                                // without exposure. Only these trace the
+                                // first hit again for the AOVs.
+                                if let Some(aovs) = aovs.as_mut() {
+                                    let mut rec: HitRecord = Default::default();
+                                    let hit = world.hit(&r, 0.001, f32::MAX, &mut rec);
+                                    aovs.add_sample(i, j, (s, t), &r, hit.then_some(&rec), cam);
+                                }
+                                let c = integrator::debug_colour(
+                                    settings.integrator,
+                                    &r,
+                                    world,
+                                    settings,
+                                    cam.fd,
+                                    sampler,
+                                );
+                                (c, Aov::Emission)
+                            } else {
+                                let (c, pass, hit) = ray_colour(
+                                    &r,
+                                    world,
+                                    settings.max_depth,
+                                    settings.rr_depth,
+                                    sampler,
+                                );
+                                if let Some(aovs) = aovs.as_mut() {
+                                    aovs.add_sample(i, j, (s, t), &r, hit.as_ref(), cam);
+                                }
+                                (c.mul(&[weight]).mulf(cam.exposure()), pass)
+                            }
+                        }
+                        None => (Default::default(), Aov::Emission),
+                    };
+                    fb.pixels[idx].add_sample(&c);
+                    fb.splat(x, y, &c, filter);
+                    if let Some(aovs) = aovs.as_mut() {
+                        aovs.splat(x, y, &c, pass, filter);
+                    }
+                }
+            }
+        }
+        on_pass(fb)?;
+    }
+
+    Ok(())
+}
+
+// Renders one frame of a scene, or a still without one, to a framebuffer
+// with the sampler, filter and integrator named in settings, and without
+// AOVs. The framebuffer is twice as wide or tall as the image for a stereo
+// camera.
+pub fn render_scene(
+    scene: &Scene,
+    settings: &Settings,
+    frame: Option<f32>,
+) -> Result<Framebuffer, RenderError> {
+    let renderer = Renderer::new(scene).settings(settings.clone());
+    match frame {
+        Some(f) => renderer.frame(f),
+        None => renderer,
+    }
+    .render_framebuffer()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::Aov;
+    use crate::aov::Aovs;
+    use crate::filter::Filters;
+    use crate::sampler::Samplers;
+    use crate::scene;
+    use crate::settings::Settings;
+    use crate::vec::Colour;
+    use crate::vec::Point;
+    use crate::vec::Vec;
+
+    #[test]
+    fn region() {
+        assert!("1,2,3".parse::<Region>().is_err());
+        assert!("4,0,2,2".parse::<Region>().is_err());
+        let r: Region = "1, 0, 3, 1".parse().unwrap();
+
+        // Pixel (i, j) holds i + 10 j, with j counted up from the bottom.
+        let mut fb = Framebuffer::new(4, 3);
+        for (idx, px) in fb.pixels.iter_mut().enumerate() {
+            px.sum = Colour {
+                x: idx as f32 % 4.0 + 10.0 * (idx / 4) as f32,
+                ..Default::default()
+            };
+            px.weight = 1.0;
+        }
+        let crop = fb.region(&r, false);
+        assert_eq!((crop.width, crop.height), (2, 1));
+        assert_eq!(crop.at(0, 0).colour().x, 21.0);
+        assert_eq!(crop.at(1, 0).colour().x, 22.0);
+
+        let full = fb.region(&r, true);
+        assert_eq!((full.width, full.height), (4, 3));
+        assert_eq!(full.at(2, 2).colour().x, 22.0);
+        assert_eq!(full.at(2, 1).colour().x, 0.0);
+        assert_eq!(full.at(0, 2).colour().x, 0.0);
+    }
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let mut fb = Framebuffer::new(3, 2);
+        for (idx, px) in fb.pixels.iter_mut().enumerate() {
+            let v = idx as f32;
+            px.sum = Colour {
+                x: v,
+                y: v + 0.25,
+                z: v + 0.5,
+            };
+            px.weight = 0.5 + v;
+            px.abs_weight = 1.5 + v;
+            px.n = idx as u32;
+            px.own = Colour {
+                x: -v,
+                ..Default::default()
+            };
+            px.mean = v * 0.1;
+            px.m2 = v * 0.01;
+        }
+        let info = CheckpointInfo {
+            seed: 7,
+            sampler: String::from("halton"),
+            filter: String::from("gaussian"),
+            filter_radius: 1.5,
+            n_samples: 16,
+            scene: 0x5ce4e,
+            integrator: String::from("path"),
+            max_depth: 50,
+            rr_depth: 3,
+            ao_distance: 1.0,
+        };
+        let path = std::env::temp_dir().join("checkpoint_round_trip.ckpt");
+        let path = path.to_str().unwrap();
+        fb.save_checkpoint(path, &info).unwrap();
+
+        let (loaded, saved) = Framebuffer::load_checkpoint(path).unwrap();
+        assert_eq!(saved, info);
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        for (a, b) in fb.pixels.iter().zip(&loaded.pixels) {
+            assert_eq!(
+                (a.sum, a.weight, a.abs_weight),
+                (b.sum, b.weight, b.abs_weight)
+            );
+            assert_eq!((a.n, a.own), (b.n, b.own));
+            assert_eq!((a.mean, a.m2), (b.mean, b.m2));
+        }
+
+        // More samples are fine, except where the sampler's strata depend on
+        // the count; anything else has to match.
+        let more = CheckpointInfo {
+            n_samples: 64,
+            ..info.clone()
+        };
+        assert!(more.can_resume(&saved).is_ok());
+        // The AO distance only matters to the AO integrator.
+        let ao = CheckpointInfo {
+            ao_distance: 2.0,
+            ..info.clone()
+        };
+        assert!(ao.can_resume(&saved).is_ok());
+        let stratified = CheckpointInfo {
+            sampler: String::from("stratified"),
+            ..info.clone()
+        };
+        assert!(CheckpointInfo {
+            n_samples: 64,
+            ..stratified.clone()
+        }
+        .can_resume(&stratified)
+        .is_err());
+        for other in [
+            CheckpointInfo {
+                seed: 8,
+                ..info.clone()
+            },
+            CheckpointInfo {
+                filter_radius: 2.0,
+                ..info.clone()
+            },
+            CheckpointInfo {
+                scene: 0x5ce4f,
+                ..info.clone()
+            },
+            CheckpointInfo {
+                integrator: String::from("ao"),
+                ..info.clone()
+            },
+            CheckpointInfo {
+                max_depth: 8,
+                ..info.clone()
+            },
+            CheckpointInfo {
+                rr_depth: 0,
+                ..info.clone()
+            },
+            stratified,
+        ] {
+            assert!(other.can_resume(&saved).is_err());
+        }
+
+        // A truncated file, or one claiming more pixels than it holds, is
+        // rejected before anything is allocated.
+        let bytes = std::fs::read(path).unwrap();
+        std::fs::write(path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(Framebuffer::load_checkpoint(path).is_err());
+        let mut huge = bytes.clone();
+        huge[8..16].copy_from_slice(&[0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff, 0x7f]);
+        std::fs::write(path, &huge).unwrap();
+        assert!(Framebuffer::load_checkpoint(path).is_err());
+        std::fs::write(path, &bytes[..20]).unwrap();
+        assert!(Framebuffer::load_checkpoint(path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn adaptive_sampling() {
+        let s = scene::parse(
+            "lookfrom 0 1 4
+            sphere 0 -100 0 100 lambertian 0.5 0.5 0.5",
+        )
+        .unwrap();
+        let settings = Settings {
+            n_samples: 60,
+            min_samples: 8,
+            adaptive_threshold: 0.02,
+            ..Default::default()
+        };
+        let cam = s.camera.build(2.0, &s.world).unwrap().0;
+        let mut fb = Framebuffer::new(16, 8);
+        let filter = Filters::new("box", None).unwrap();
+        let mut sampler = Samplers::new("independent", 1, 60).unwrap();
+        render(
+            &settings,
+            &s.world,
+            &cam,
+            &mut sampler,
+            &filter,
+            &mut fb,
+            None,
+            |_| true,
+            |_| Ok(()),
+        )
+        .unwrap();
+
+        // Passes add min_samples at a time up to the limit, never past it.
+        // The smooth sky at the top converges after the first pass, while
+        // some of the noisy ground goes all the way.
+        assert!(fb.pixels.iter().all(|px| px.n % 8 == 0 || px.n == 60));
+        assert!(fb.pixels.iter().all(|px| px.n <= 60));
+        assert!((0..16).all(|i| fb.at(i, 7).n == 8));
+        assert!(fb.pixels.iter().any(|px| px.n == 60));
+
+        let path = std::env::temp_dir().join("adaptive_sampling.pgm");
+        let path = path.to_str().unwrap();
+        fb.write_spp_map(path, settings.n_samples).unwrap();
+        let map = std::fs::read_to_string(path).unwrap();
+        let values: std::vec::Vec<&str> = map.split_whitespace().skip(4).collect();
+        assert_eq!(values.len(), 16 * 8);
+        // 8 of 60 samples in the top row.
+        assert_eq!(values[0], "34");
+    }
+
+    #[test]
+    fn negative_lobes_at_one_sample() {
+        // A black sphere against the sky. Ringing around its edge may
+        // overshoot a little, but no pixel should blow up.
+        let s = scene::parse(
+            "lookfrom 0 0 4
+            sphere 0 0 0 0.3 lambertian 0 0 0",
+        )
+        .unwrap();
+        let settings = Settings {
+            n_samples: 1,
+            ..Default::default()
+        };
+        let cam = s.camera.build(1.0, &s.world).unwrap().0;
+        for seed in 0..64 {
+            let mut fb = Framebuffer::new(32, 32);
+            let filter = Filters::new("lanczos", None).unwrap();
+            let mut sampler = Samplers::new("independent", seed, 1).unwrap();
+            render(
+                &settings,
+                &s.world,
+                &cam,
+                &mut sampler,
+                &filter,
+                &mut fb,
+                None,
+                |_| true,
+                |_| Ok(()),
+            )
+            .unwrap();
+            for px in &fb.pixels {
+                let c = px.colour();
+                assert!(c.x > -0.5 && c.z < 1.5, "{c:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn light_passes_sum_to_beauty() {
+        let s = scene::parse(
+            "lookfrom 0 1 4
+            sphere -1 0.5 0 0.5 metal 0.8 0.8 0.8 0.2
+            sphere 1 0.5 0 0.5 dielectric 1.5
+            sphere 0 -100 0 100 lambertian 0.5 0.5 0.5",
+        )
+        .unwrap();
+        let cam = s.camera.build(2.0, &s.world).unwrap().0;
+        let passes = [
+            Aov::Emission,
+            Aov::DiffuseDirect,
+            Aov::DiffuseIndirect,
+            Aov::Specular,
+            Aov::Transmission,
+        ];
+        let render_passes = |filter: &str, n_samples: i32| {
+            let settings = Settings {
+                n_samples,
+                ..Default::default()
+            };
+            let mut aovs = Aovs::new(&passes, &s.world, 16, 8);
+            let mut fb = Framebuffer::new(16, 8);
+            let filter = Filters::new(filter, None).unwrap();
+            let mut sampler = Samplers::new("independent", 1, n_samples as u32).unwrap();
+            render(
+                &settings,
+                &s.world,
+                &cam,
+                &mut sampler,
+                &filter,
+                &mut fb,
+                Some(&mut aovs),
+                |_| true,
+                |_| Ok(()),
+            )
+            .unwrap();
+            (fb, aovs)
+        };
+
+        // At one sample Lanczos leaves some pixels short of weight, and
+        // those have to fall back in the passes just as in the beauty.
+        let starved = |px: &Pixel| px.n > 0 && px.weight < MIN_WEIGHT * px.abs_weight;
+        for (filter, n_samples) in [("lanczos", 1), ("mitchell", 8)] {
+            let (fb, aovs) = render_passes(filter, n_samples);
+            assert_eq!(fb.pixels.iter().any(starved), filter == "lanczos");
+            for (idx, px) in fb.pixels.iter().enumerate() {
+                let sum = passes.iter().fold(Colour::default(), |sum, p| {
+                    let pass = &aovs.layer(*p).unwrap().pixels[idx];
+                    // Passes share the beauty's filter weights and samples.
+                    assert_eq!((pass.weight, pass.n), (px.weight, px.n));
+                    sum.add(&[pass.colour()])
+                });
+                let c = px.colour();
+                assert!(sum.sub(&[c]).len() < 1e-4, "{filter}: {sum:?} {c:?}");
+            }
+        }
+        let (_, aovs) = render_passes("mitchell", 8);
+        // Every lobe shows up somewhere in this scene.
+        for p in passes {
+            let total: f32 = aovs
+                .layer(p)
+                .unwrap()
+                .pixels
+                .iter()
+                .map(|px| px.sum.len())
+                .sum();
+            assert!(total > 0.0, "{p:?} is empty");
+        }
+    }
+
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        // Grey spheres lit by the sky, where paths bounce between the two
+        // before escaping. Terminating them early must not change the mean
+        // radiance, only its noise.
+        let s = scene::parse(
+            "sphere 0 0.5 0 0.5 lambertian 0.7 0.7 0.7
+            sphere 0 -100 0 100 lambertian 0.5 0.5 0.5",
+        )
+        .unwrap();
+        let r = Ray {
+            orig: Point {
+                x: 0.0,
+                y: 1.0,
+                z: 4.0,
+            },
+            dir: Vec {
+                x: 0.0,
+                y: -0.7,
+                z: -4.0,
+            },
+        };
+        let n = 20000;
+        // Mean luminance and its standard error over n paths.
+        let estimate = |rr_depth: i32| {
+            let mut sampler = Samplers::new("independent", 11, 1).unwrap();
+            let (mut sum, mut sum_sq) = (0.0, 0.0);
+            for i in 0..n {
+                sampler.start_sample(0, 0, i);
+                let (c, _, _, _) = trace(&r, &s.world, 50, rr_depth, &mut sampler);
+                let l = luminance(&c) as f64;
+                sum += l;
+                sum_sq += l * l;
+            }
+            let mean = sum / n as f64;
+            let var = sum_sq / n as f64 - mean * mean;
+            (mean, (var / n as f64).sqrt())
+        };
+        let (with, se_with) = estimate(0);
+        let (without, se_without) = estimate(50);
+        let bound = 4.0 * (se_with * se_with + se_without * se_without).sqrt();
+        assert!(
+            (with - without).abs() < bound,
+            "{with} vs {without} +- {bound}"
+        );
+    }
+}
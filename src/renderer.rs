@@ -0,0 +1,608 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::aov;
+use crate::aov::Aov;
+use crate::aov::Aovs;
+use crate::denoise;
+use crate::filter::Filter;
+use crate::filter::Filters;
+use crate::render;
+use crate::render::CheckpointInfo;
+use crate::render::Framebuffer;
+use crate::render::Image;
+use crate::render::Progress;
+use crate::sampler::Samplers;
+use crate::scene;
+use crate::scene::CameraDesc;
+use crate::scene::Scene;
+use crate::settings::Settings;
+use crate::stats;
+
+// Why a render didn't produce an image.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenderError {
+    // The cancel flag was set before the render finished.
+    Cancelled,
+    // The image size or sample count is out of range, the camera, filter
+    // or sampler can't be built from the scene and settings, or a
+    // checkpoint doesn't fit the render resuming it.
+    Invalid(String),
+    // The on_pass callback failed.
+    Io(String),
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RenderError::Cancelled => write!(f, "render cancelled"),
+            RenderError::Invalid(msg) | RenderError::Io(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+// Everything a render produces. The framebuffer is twice as wide or tall
+// as the image for a stereo camera.
+pub struct Frame {
+    pub framebuffer: Framebuffer,
+    // The framebuffer after denoising, when the settings ask for it.
+    pub denoised: Option<Framebuffer>,
+    // The layers asked for with Renderer::aovs, and the denoiser's guides.
+    pub aovs: Option<Aovs>,
+    // What checkpoints of the framebuffer need to resume it.
+    pub checkpoint: CheckpointInfo,
+    pub counters: stats::Counters,
+    // Time spent posing the scene and building its camera, building the
+    // BVH, rendering and denoising.
+    pub timings: stats::Timings,
+}
+
+type OnPass<'a> = dyn FnMut(&Framebuffer, &CheckpointInfo) -> std::io::Result<()> + 'a;
+
+// Renders a scene to memory. Settings default to those of the command line
+// tool; only the image, sampling, integrator and denoising options are
+// used, and output paths and writing files are left to the caller.
+//
+//     let image = Renderer::new(&scene)
+//         .settings(settings)
+//         .on_progress(|p| eprint!("\r{} lines to go", p.lines_remaining))
+//         .render()?;
+pub struct Renderer<'a> {
+    scene: &'a Scene,
+    camera: Option<CameraDesc>,
+    settings: Settings,
+    frame: Option<f32>,
+    aovs: Vec<Aov>,
+    resume: Option<(Framebuffer, CheckpointInfo)>,
+    on_progress: Box<dyn FnMut(Progress) + 'a>,
+    on_pass: Box<OnPass<'a>>,
+    on_warning: Box<dyn FnMut(&str) + 'a>,
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(scene: &'a Scene) -> Renderer<'a> {
+        Renderer {
+            scene,
+            camera: None,
+            settings: Default::default(),
+            frame: None,
+            aovs: Vec::new(),
+            resume: None,
+            on_progress: Box::new(|_| {}),
+            on_pass: Box::new(|_, _| Ok(())),
+            on_warning: Box::new(|_| {}),
+            cancel: None,
+        }
+    }
+
+    // Replaces the scene's camera, including any animation of it.
+    pub fn camera(mut self, camera: CameraDesc) -> Renderer<'a> {
+        self.camera = Some(camera);
+        self
+    }
+
+    pub fn settings(mut self, settings: Settings) -> Renderer<'a> {
+        self.settings = settings;
+        self
+    }
+
+    // Frame of an animated scene to render, which also picks the frame's
+    // noise as Settings::frame_seed does. Without it the scene renders at
+    // frame 0 with the plain seed.
+    pub fn frame(mut self, frame: f32) -> Renderer<'a> {
+        self.frame = Some(frame);
+        self
+    }
+
+    // Layers to record alongside the image. Motion is towards the next
+    // frame, so it stays zero without Renderer::frame.
+    pub fn aovs(mut self, aovs: &[Aov]) -> Renderer<'a> {
+        self.aovs = aovs.to_vec();
+        self
+    }
+
+    // Continues a checkpoint instead of starting from an empty framebuffer.
+    // The render fails with RenderError::Invalid if the checkpoint was made
+    // with another size, scene or sampling.
+    pub fn resume(mut self, fb: Framebuffer, saved: CheckpointInfo) -> Renderer<'a> {
+        self.resume = Some((fb, saved));
+        self
+    }
+
+    // Called before every scan line of every pass.
+    pub fn on_progress(mut self, f: impl FnMut(Progress) + 'a) -> Renderer<'a> {
+        self.on_progress = Box::new(f);
+        self
+    }
+
+    // Called after every pass with the framebuffer so far and the header to
+    // checkpoint it with. An error stops the render and comes back as
+    // RenderError::Io.
+    pub fn on_pass(
+        mut self,
+        f: impl FnMut(&Framebuffer, &CheckpointInfo) -> std::io::Result<()> + 'a,
+    ) -> Renderer<'a> {
+        self.on_pass = Box::new(f);
+        self
+    }
+
+    // Called with problems that don't stop the render, such as an autofocus
+    // that found nothing to focus on.
+    pub fn on_warning(mut self, f: impl FnMut(&str) + 'a) -> Renderer<'a> {
+        self.on_warning = Box::new(f);
+        self
+    }
+
+    // The render stops at the next scan line once flag is set, from the
+    // progress callback or another thread, and returns
+    // RenderError::Cancelled.
+    pub fn cancel_flag(mut self, flag: Arc<AtomicBool>) -> Renderer<'a> {
+        self.cancel = Some(flag);
+        self
+    }
+
+    // Renders the image along with its AOVs, checkpoint header and
+    // statistics.
+    pub fn render_frame(mut self) -> Result<Frame, RenderError> {
+        stats::reset();
+        let mut timings: stats::Timings = Default::default();
+        let settings = &self.settings;
+        settings.validate().map_err(RenderError::Invalid)?;
+        // Checkpoints only hold the image, so AOVs and the denoiser's guides
+        // would cover just the samples taken after resuming.
+        if self.resume.is_some() && (!self.aovs.is_empty() || settings.denoise) {
+            return Err(RenderError::Invalid(String::from(
+                "resuming can't be combined with AOVs or denoising",
+            )));
+        }
+        let filter = Filters::new(&settings.filter, settings.filter_radius)
+            .ok_or_else(|| RenderError::Invalid(format!("unknown filter: {}", settings.filter)))?;
+        let seed = settings.frame_seed(self.frame);
+        let mut sampler = Samplers::new(&settings.sampler, seed, settings.n_samples as u32)
+            .ok_or_else(|| {
+                RenderError::Invalid(format!("unknown sampler: {}", settings.sampler))
+            })?;
+
+        let start = Instant::now();
+        let frame = self.frame.unwrap_or(0.0);
+        let (camera, mut world) = self.scene.at_frame(frame);
+        let camera = self.camera.clone().unwrap_or(camera);
+        timings.scene += start.elapsed();
+        let start = Instant::now();
+        world.build_bvh();
+        timings.bvh += start.elapsed();
+        let start = Instant::now();
+        let (cam, warnings) = camera
+            .build(settings.aspect_ratio, &world)
+            .map_err(RenderError::Invalid)?;
+        for w in &warnings {
+            (self.on_warning)(w);
+        }
+        timings.scene += start.elapsed();
+
+        let (width, height) = cam
+            .stereo
+            .film_size(settings.image_width, settings.image_height());
+        let checkpoint = CheckpointInfo {
+            seed,
+            sampler: settings.sampler.clone(),
+            filter: settings.filter.clone(),
+            filter_radius: filter.radius(),
+            n_samples: settings.n_samples as u32,
+            scene: scene::fingerprint(&camera, &world),
+            integrator: format!("{:?}", settings.integrator).to_lowercase(),
+            max_depth: settings.max_depth,
+            rr_depth: settings.rr_depth,
+            ao_distance: settings.ao_distance,
+        };
+        let mut fb = match self.resume.take() {
+            Some((fb, saved)) => {
+                if fb.width != width || fb.height != height {
+                    return Err(RenderError::Invalid(format!(
+                        "the checkpoint is {}x{}, but the render is {width}x{height}",
+                        fb.width, fb.height
+                    )));
+                }
+                checkpoint
+                    .can_resume(&saved)
+                    .map_err(RenderError::Invalid)?;
+                fb
+            }
+            None => Framebuffer::new(width, height),
+        };
+
+        // The denoiser is guided by the normal and albedo AOVs.
+        let mut layers = self.aovs.clone();
+        if settings.denoise {
+            for aov in [Aov::Normal, Aov::Albedo] {
+                if !layers.contains(&aov) {
+                    layers.push(aov);
+                }
+            }
+        }
+        let start = Instant::now();
+        let mut aovs = (!layers.is_empty()).then(|| {
+            let mut aovs = Aovs::new(&layers, &world, width, height);
+            aovs.save = self.aovs.clone();
+            if let (Some(f), true) = (self.frame, layers.contains(&Aov::Motion)) {
+                let (camera, next_world) = self.scene.at_frame(f + 1.0);
+                let camera = self.camera.clone().unwrap_or(camera);
+                if let Ok((cam, _)) = camera.build(settings.aspect_ratio, &next_world) {
+                    aovs.next = Some(aov::NextFrame {
+                        cam,
+                        offsets: self.scene.animation.motion(f, world.objects.len()),
+                    });
+                }
+            }
+            aovs
+        });
+        timings.scene += start.elapsed();
+
+        let cancel = self.cancel.unwrap_or_default();
+        let mut cancelled = false;
+        let start = Instant::now();
+        render::render(
+            settings,
+            &world,
+            &cam,
+            &mut sampler,
+            &filter,
+            &mut fb,
+            aovs.as_mut(),
+            |p| {
+                (self.on_progress)(p);
+                cancelled = cancel.load(Ordering::Relaxed);
+                !cancelled
+            },
+            |fb| (self.on_pass)(fb, &checkpoint),
+        )
+        .map_err(|e| match cancelled {
+            true => RenderError::Cancelled,
+            false => RenderError::Io(e.to_string()),
+        })?;
+        timings.render += start.elapsed();
+
+        let start = Instant::now();
+        let denoised = match (&aovs, settings.denoise) {
+            (Some(aovs), true) => Some(denoise::denoise(
+                &fb,
+                aovs.layer(Aov::Normal).unwrap(),
+                aovs.layer(Aov::Albedo).unwrap(),
+                settings.denoise_iterations,
+            )),
+            _ => None,
+        };
+        timings.denoise += start.elapsed();
+
+        Ok(Frame {
+            framebuffer: fb,
+            denoised,
+            aovs,
+            checkpoint,
+            counters: stats::counters(),
+            timings,
+        })
+    }
+
+    // Renders into a framebuffer, which keeps the sample counts and filter
+    // weights along with the colours. stats::counters() then reports this
+    // render alone.
+    pub fn render_framebuffer(self) -> Result<Framebuffer, RenderError> {
+        Ok(self.render_frame()?.framebuffer)
+    }
+
+    // The denoised image when the settings ask for denoising.
+    pub fn render(self) -> Result<Image, RenderError> {
+        let frame = self.render_frame()?;
+        Ok(frame.denoised.unwrap_or(frame.framebuffer).image())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene;
+
+    fn scene() -> Scene {
+        scene::parse(
+            "lookfrom 0 1 4
+            sphere 0 0.5 0 0.5 lambertian 0.7 0.3 0.3
+            sphere 0 -100 0 100 lambertian 0.5 0.5 0.5",
+        )
+        .unwrap()
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            image_width: 8,
+            aspect_ratio: 2.0,
+            n_samples: 4,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn renders_to_memory() {
+        let s = scene();
+        let mut lines = 0;
+        let image = Renderer::new(&s)
+            .settings(settings())
+            .on_progress(|p| {
+                assert_eq!((p.pass, p.height), (1, 4));
+                lines += 1;
+            })
+            .render()
+            .unwrap();
+        assert_eq!(lines, 4);
+        assert_eq!(stats::counters().samples, 8 * 4 * 4);
+        assert_eq!((image.width, image.height), (8, 4));
+        assert_eq!(image.data.len(), 8 * 4 * 3);
+        // Sky at the top, ground at the bottom.
+        assert!(image.pixel(0, 0).z > image.pixel(0, 3).z);
+        assert_eq!(
+            image,
+            render::render_scene(&s, &settings(), None).unwrap().image()
+        );
+    }
+
+    #[test]
+    fn cancels() {
+        let s = scene();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = cancel.clone();
+        let mut lines = 0;
+        let result = Renderer::new(&s)
+            .settings(settings())
+            .cancel_flag(cancel)
+            .on_progress(|_| {
+                lines += 1;
+                flag.store(true, Ordering::Relaxed);
+            })
+            .render();
+        assert_eq!(result, Err(RenderError::Cancelled));
+        assert_eq!(lines, 1);
+
+        let result = Renderer::new(&s)
+            .settings(Settings {
+                filter: String::from("sinc"),
+                ..settings()
+            })
+            .render();
+        assert_eq!(
+            result,
+            Err(RenderError::Invalid(String::from("unknown filter: sinc")))
+        );
+    }
+
+    #[test]
+    fn resumes() {
+        let s = scene();
+        let half = || {
+            let mut passes = 0;
+            let mut saved = None;
+            let frame = Renderer::new(&s)
+                .settings(Settings {
+                    n_samples: 2,
+                    ..settings()
+                })
+                .on_pass(|_, info| {
+                    passes += 1;
+                    saved = Some(info.clone());
+                    Ok(())
+                })
+                .render_frame()
+                .unwrap();
+            assert_eq!(passes, 1);
+            assert_eq!(saved.as_ref(), Some(&frame.checkpoint));
+            (frame.framebuffer, frame.checkpoint)
+        };
+        let resume = |settings: Settings, aovs: &[Aov]| {
+            let (fb, saved) = half();
+            Renderer::new(&s)
+                .settings(settings)
+                .aovs(aovs)
+                .resume(fb, saved)
+                .render()
+        };
+
+        // Resuming takes the remaining samples, so it matches rendering
+        // them all at once.
+        assert_eq!(
+            resume(settings(), &[]),
+            Renderer::new(&s).settings(settings()).render()
+        );
+        let seed = Settings {
+            seed: 1,
+            ..settings()
+        };
+        assert_eq!(
+            resume(seed, &[]),
+            Err(RenderError::Invalid(String::from(
+                "the checkpoint was rendered with seed 0, not 1"
+            )))
+        );
+        let wide = Settings {
+            image_width: 10,
+            ..settings()
+        };
+        assert_eq!(
+            resume(wide, &[]),
+            Err(RenderError::Invalid(String::from(
+                "the checkpoint is 8x4, but the render is 10x5"
+            )))
+        );
+        assert_eq!(
+            resume(settings(), &[Aov::Normal]),
+            Err(RenderError::Invalid(String::from(
+                "resuming can't be combined with AOVs or denoising"
+            )))
+        );
+    }
+
+    #[test]
+    fn records_aovs() {
+        let s = scene();
+        let frame = Renderer::new(&s)
+            .settings(Settings {
+                denoise: true,
+                ..settings()
+            })
+            .aovs(&[Aov::Depth])
+            .render_frame()
+            .unwrap();
+        let aovs = frame.aovs.unwrap();
+        // The denoiser's guides are recorded but not saved.
+        assert_eq!(aovs.save, [Aov::Depth]);
+        for aov in [Aov::Depth, Aov::Normal, Aov::Albedo] {
+            assert!(aovs.layer(aov).is_some());
+        }
+        assert!(frame.denoised.is_some());
+        assert_eq!(frame.counters.samples, 8 * 4 * 4);
+    }
+
+    #[test]
+    fn stops_when_a_pass_fails() {
+        let s = scene();
+        let result = Renderer::new(&s)
+            .settings(settings())
+            .on_pass(|_, _| Err(std::io::Error::other("disk full")))
+            .render();
+        assert_eq!(result, Err(RenderError::Io(String::from("disk full"))));
+    }
+
+    #[test]
+    fn warns() {
+        // The centre of the image looks into the sky.
+        let s = scene::parse(
+            "lookfrom 0 1 4
+            lookat 0 2 0
+            autofocus
+            sphere 0 -100 0 100 lambertian 0.5 0.5 0.5",
+        )
+        .unwrap();
+        let mut warnings = std::vec::Vec::new();
+        Renderer::new(&s)
+            .settings(settings())
+            .on_warning(|w| warnings.push(String::from(w)))
+            .render()
+            .unwrap();
+        assert_eq!(
+            warnings,
+            ["autofocus: nothing under the image centre, keeping focus distance"]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        let s = scene();
+        let cases = [
+            (
+                Settings {
+                    image_width: 0,
+                    ..settings()
+                },
+                "image width must be positive: 0",
+            ),
+            (
+                Settings {
+                    aspect_ratio: 0.0,
+                    ..settings()
+                },
+                "aspect ratio must be positive: 0",
+            ),
+            (
+                Settings {
+                    aspect_ratio: f32::NAN,
+                    ..settings()
+                },
+                "aspect ratio must be positive: NaN",
+            ),
+            (
+                Settings {
+                    aspect_ratio: f32::INFINITY,
+                    ..settings()
+                },
+                "aspect ratio must be positive: inf",
+            ),
+            (
+                Settings {
+                    aspect_ratio: 16.0,
+                    ..settings()
+                },
+                "image height is zero: width 8 at aspect ratio 16",
+            ),
+            (
+                Settings {
+                    image_width: 100_000,
+                    aspect_ratio: 1.0,
+                    ..settings()
+                },
+                "image too large: 100000x100000",
+            ),
+            (
+                Settings {
+                    n_samples: 0,
+                    ..settings()
+                },
+                "samples per pixel must be positive: 0",
+            ),
+        ];
+        for (settings, msg) in cases {
+            assert_eq!(
+                Renderer::new(&s).settings(settings).render(),
+                Err(RenderError::Invalid(String::from(msg)))
+            );
+        }
+    }
+
+    #[test]
+    fn seeds_frames() {
+        let s = scene();
+        let render = |frame: Option<f32>| {
+            let r = Renderer::new(&s).settings(settings());
+            match frame {
+                Some(f) => r.frame(f).render().unwrap(),
+                None => r.render().unwrap(),
+            }
+        };
+        // The scene isn't animated, so only the noise differs.
+        assert_ne!(render(None), render(Some(0.0)));
+        assert_ne!(render(Some(0.0)), render(Some(1.0)));
+        assert_eq!(render(Some(1.0)), render(Some(1.0)));
+        let settings = settings();
+        assert_eq!(settings.frame_seed(None), settings.seed);
+        assert_eq!(
+            settings.frame_seed(Some(-2.0)),
+            crate::util::splitmix64(settings.seed ^ -2i32 as u64)
+        );
+        assert_ne!(
+            settings.frame_seed(Some(1.0)),
+            settings.frame_seed(Some(1.5))
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use crate::util;
+
+// A sampler hands out the random numbers for one sample of one pixel. Each
+// call to get_1d/get_2d consumes the next dimension of the sample, so callers
+// must ask for their dimensions in the same order for every sample.
+pub trait Sampler {
+    fn start_sample(&mut self, px: i32, py: i32, index: u32);
+    fn get_1d(&mut self) -> f32;
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+fn pixel_key(px: i32, py: i32) -> u64 {
+    ((py as u32 as u64) << 32) | px as u32 as u64
+}
+
+fn hash(a: u64, b: u64) -> u64 {
+    util::splitmix64(a ^ util::splitmix64(b))
+}
+
+// Kensler's hash based permutation: element i of a random permutation of
+// 0..l chosen by p, computed without storing the permutation.
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    i.wrapping_add(p) % l
+}
+
+pub struct Independent {
+    seed: u64,
+    rng: util::Rng,
+}
+
+impl Independent {
+    pub fn new(seed: u64) -> Independent {
+        Independent {
+            seed,
+            rng: util::Rng::new(seed),
+        }
+    }
+}
+
+impl Sampler for Independent {
+    fn start_sample(&mut self, px: i32, py: i32, index: u32) {
+        self.rng = util::Rng::for_sample(self.seed, pixel_key(px, py), index as u64);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.rng.next_f32(), self.rng.next_f32())
+    }
+}
+
+// Jittered stratification. Every dimension is split into spp strata (a
+// sx * sy grid for 2D), and each dimension visits its strata in its own
+// random order so the dimensions stay uncorrelated.
+pub struct Stratified {
+    seed: u64,
+    spp: u32,
+    pixel: u64,
+    index: u32,
+    dim: u64,
+    rng: util::Rng,
+}
+
+impl Stratified {
+    pub fn new(seed: u64, spp: u32) -> Stratified {
+        Stratified {
+            seed,
+            spp: spp.max(1),
+            pixel: 0,
+            index: 0,
+            dim: 0,
+            rng: util::Rng::new(seed),
+        }
+    }
+
+    fn next_stratum(&mut self, n: u32) -> u32 {
+        let p = hash(self.pixel, self.dim) as u32;
+        self.dim += 1;
+        permutation_element(self.index % n, n, p)
+    }
+}
+
+impl Sampler for Stratified {
+    fn start_sample(&mut self, px: i32, py: i32, index: u32) {
+        self.pixel = hash(self.seed, pixel_key(px, py));
+        self.index = index;
+        self.dim = 0;
+        self.rng = util::Rng::for_sample(self.seed, pixel_key(px, py), index as u64);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let stratum = self.next_stratum(self.spp);
+        ((stratum as f32 + self.rng.next_f32()) / self.spp as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let sx = (self.spp as f32).sqrt() as u32;
+        let sy = self.spp / sx;
+        let stratum = self.next_stratum(sx * sy);
+        let x = ((stratum % sx) as f32 + self.rng.next_f32()) / sx as f32;
+        let y = ((stratum / sx) as f32 + self.rng.next_f32()) / sy as f32;
+        (x.min(ONE_MINUS_EPSILON), y.min(ONE_MINUS_EPSILON))
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+// Radical inverse of a in the given base with every digit position shifted
+// by its own random offset. Trailing zero digits are scrambled as well, so
+// this keeps going until the digits drop below f32 precision.
+fn scrambled_radical_inverse(mut a: u32, base: u32, seed: u64) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_bi = inv_base;
+    let mut v = 0.0;
+    let mut i = 0;
+    while inv_bi > 1e-8 {
+        let shift = (hash(seed, i) % base as u64) as u32;
+        let digit = (a % base + shift) % base;
+        v += digit as f64 * inv_bi;
+        inv_bi *= inv_base;
+        a /= base;
+        i += 1;
+    }
+    (v as f32).min(ONE_MINUS_EPSILON)
+}
+
+// Per-pixel Halton sequence with random digit scrambling. Dimensions past
+// the prime table fall back to independent random numbers.
+pub struct Halton {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dim: usize,
+    rng: util::Rng,
+}
+
+impl Halton {
+    pub fn new(seed: u64) -> Halton {
+        Halton {
+            seed,
+            pixel: 0,
+            index: 0,
+            dim: 0,
+            rng: util::Rng::new(seed),
+        }
+    }
+}
+
+impl Sampler for Halton {
+    fn start_sample(&mut self, px: i32, py: i32, index: u32) {
+        self.pixel = hash(self.seed, pixel_key(px, py));
+        self.index = index;
+        self.dim = 0;
+        self.rng = util::Rng::for_sample(self.seed, pixel_key(px, py), index as u64);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dim = self.dim;
+        self.dim += 1;
+        if dim < PRIMES.len() {
+            scrambled_radical_inverse(self.index, PRIMES[dim], hash(self.pixel, dim as u64))
+        } else {
+            self.rng.next_f32()
+        }
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn sobol(index: u32, dim: u32) -> u32 {
+    if dim == 0 {
+        return index.reverse_bits();
+    }
+    // Second Sobol dimension: primitive polynomial x + 1, m1 = 1.
+    let mut v = 1u32 << 31;
+    let mut x = 0;
+    for bit in 0..32 {
+        if (index >> bit) & 1 == 1 {
+            x ^= v;
+        }
+        v ^= v >> 1;
+    }
+    x
+}
+
+// Owen-scrambled Sobol following Burley, "Practical Hash-based Owen
+// Scrambling" (2020). Every 1D/2D request draws from the first one/two Sobol
+// dimensions with its own index shuffle and scramble, which pads the
+// sequence out to any number of dimensions.
+pub struct Sobol {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dim: u64,
+}
+
+impl Sobol {
+    pub fn new(seed: u64) -> Sobol {
+        Sobol {
+            seed,
+            pixel: 0,
+            index: 0,
+            dim: 0,
+        }
+    }
+
+    fn next_seed(&mut self) -> u64 {
+        let s = hash(self.pixel, self.dim);
+        self.dim += 1;
+        s
+    }
+}
+
+impl Sampler for Sobol {
+    fn start_sample(&mut self, px: i32, py: i32, index: u32) {
+        self.pixel = hash(self.seed, pixel_key(px, py));
+        self.index = index;
+        self.dim = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let s = self.next_seed();
+        let i = nested_uniform_scramble(self.index, s as u32);
+        let x = nested_uniform_scramble(sobol(i, 0), (s >> 32) as u32);
+        util::u32_to_f32(x)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let s = self.next_seed();
+        let i = nested_uniform_scramble(self.index, s as u32);
+        let x = nested_uniform_scramble(sobol(i, 0), (s >> 32) as u32);
+        let y = nested_uniform_scramble(sobol(i, 1), util::splitmix64(s) as u32);
+        (util::u32_to_f32(x), util::u32_to_f32(y))
+    }
+}
+
+pub enum Samplers {
+    Independent(Independent),
+    Stratified(Stratified),
+    Halton(Halton),
+    Sobol(Sobol),
+}
+
+impl Samplers {
+    pub fn new(name: &str, seed: u64, spp: u32) -> Option<Samplers> {
+        match name {
+            "independent" => Some(Samplers::Independent(Independent::new(seed))),
+            "stratified" => Some(Samplers::Stratified(Stratified::new(seed, spp))),
+            "halton" => Some(Samplers::Halton(Halton::new(seed))),
+            "sobol" => Some(Samplers::Sobol(Sobol::new(seed))),
+            _ => None,
+        }
+    }
+}
+
+impl Sampler for Samplers {
+    fn start_sample(&mut self, px: i32, py: i32, index: u32) {
+        match self {
+            Samplers::Independent(s) => s.start_sample(px, py, index),
+            Samplers::Stratified(s) => s.start_sample(px, py, index),
+            Samplers::Halton(s) => s.start_sample(px, py, index),
+            Samplers::Sobol(s) => s.start_sample(px, py, index),
+        }
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        match self {
+            Samplers::Independent(s) => s.get_1d(),
+            Samplers::Stratified(s) => s.get_1d(),
+            Samplers::Halton(s) => s.get_1d(),
+            Samplers::Sobol(s) => s.get_1d(),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        match self {
+            Samplers::Independent(s) => s.get_2d(),
+            Samplers::Stratified(s) => s.get_2d(),
+            Samplers::Halton(s) => s.get_2d(),
+            Samplers::Sobol(s) => s.get_2d(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // With 16 samples every sampler except the independent one should put
+    // exactly one sample in each cell of a 4x4 grid over the first 2D dimension.
+    fn assert_stratified_2d(mut s: Samplers) {
+        let mut cells = [0; 16];
+        for i in 0..16 {
+            s.start_sample(3, 5, i);
+            let (x, y) = s.get_2d();
+            assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+            cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+        }
+        assert_eq!(cells, [1; 16]);
+    }
+
+    #[test]
+    fn low_discrepancy_2d() {
+        assert_stratified_2d(Samplers::Stratified(Stratified::new(1, 16)));
+        assert_stratified_2d(Samplers::Sobol(Sobol::new(1)));
+    }
+
+    #[test]
+    fn halton_1d_is_stratified() {
+        let mut s = Samplers::Halton(Halton::new(1));
+        let mut cells = [0; 8];
+        for i in 0..8 {
+            s.start_sample(0, 0, i);
+            cells[(s.get_1d() * 8.0) as usize] += 1;
+        }
+        assert_eq!(cells, [1; 8]);
+    }
+}
@@ -0,0 +1,680 @@
+use crate::animation;
+use crate::camera;
+use crate::dielectric::Dielectric;
+use crate::hittable::Hittable;
+use crate::hittable::HittableList;
+use crate::hittable::Hittables;
+use crate::lambertian::Lambertian;
+use crate::lens;
+use crate::material::Materials;
+use crate::metal::Metal;
+use crate::sphere::Sphere;
+use crate::util;
+use crate::vec;
+use crate::vec::Colour;
+use crate::vec::Point;
+use crate::vec::Vec;
+
+// Everything needed to build a camera once the image aspect ratio is known.
+#[derive(Clone)]
+pub struct CameraDesc {
+    pub projection: String,
+    pub lf: Point,
+    pub la: Point,
+    pub vup: Vec,
+    pub vfov: f32,
+    pub ap: f32,
+    pub fd: f32,
+    // When focal_length is set the perspective camera is built from physical
+    // parameters (millimetres) instead of vfov and aperture.
+    pub focal_length: Option<f32>,
+    pub f_number: f32,
+    pub sensor_width: f32,
+    // Exposure is applied when either is set; the other defaults to
+    // ISO 100 or 1/100 s.
+    pub iso: Option<f32>,
+    pub shutter: Option<f32>,
+    pub autofocus: bool,
+    // Lens shift as fractions of the image size, and focal plane tilt and
+    // swing in degrees.
+    pub shift_x: f32,
+    pub shift_y: f32,
+    pub tilt: f32,
+    pub swing: f32,
+    // Lens description file for a lens system camera, whose film is
+    // sensor_width wide. Replaces the thin lens and aperture shape.
+    pub lens: Option<String>,
+    // Polygonal aperture when blades >= 3, or an image mask when given.
+    pub blades: u32,
+    pub blade_rotation: f32,
+    pub aperture_mask: Option<String>,
+    pub ortho_height: f32,
+    pub fisheye_fov: f32,
+    pub fisheye_mapping: camera::FisheyeMapping,
+    pub stereo: camera::StereoLayout,
+    pub iod: f32,
+    // Defaults to the focus distance, or parallel eyes for panoramas.
+    pub convergence: Option<f32>,
+}
+
+impl Default for CameraDesc {
+    fn default() -> CameraDesc {
+        CameraDesc {
+            projection: String::from("perspective"),
+            lf: Point {
+                x: 13.0,
+                y: 2.0,
+                z: 3.0,
+            },
+            la: Default::default(),
+            vup: Vec {
+                y: 1.0,
+                ..Default::default()
+            },
+            vfov: 20.0,
+            ap: 0.1,
+            fd: 10.0,
+            focal_length: None,
+            f_number: 8.0,
+            sensor_width: 36.0,
+            iso: None,
+            shutter: None,
+            autofocus: false,
+            shift_x: 0.0,
+            shift_y: 0.0,
+            tilt: 0.0,
+            swing: 0.0,
+            lens: None,
+            blades: 0,
+            blade_rotation: 0.0,
+            aperture_mask: None,
+            ortho_height: 4.0,
+            fisheye_fov: 180.0,
+            fisheye_mapping: camera::FisheyeMapping::Equidistant,
+            stereo: camera::StereoLayout::Mono,
+            iod: 0.064,
+            convergence: None,
+        }
+    }
+}
+
+impl CameraDesc {
+    // Builds the camera for an image of aspect ratio ar, along with warnings
+    // about options that couldn't take effect, such as an autofocus that
+    // found nothing to focus on.
+    pub fn build(
+        &self,
+        ar: f32,
+        world: &HittableList,
+    ) -> Result<(camera::Camera, std::vec::Vec<String>), String> {
+        let mut warnings = std::vec::Vec::new();
+        let (lf, la, vup) = (&self.lf, &self.la, &self.vup);
+        let mut cam = match (self.projection.as_str(), self.focal_length) {
+            ("perspective", Some(focal_length)) => Ok(camera::init_physical(
+                lf,
+                la,
+                vup,
+                focal_length,
+                self.f_number,
+                self.sensor_width,
+                ar,
+                self.fd,
+            )),
+            ("perspective", None) => Ok(camera::init(lf, la, vup, self.vfov, ar, self.ap, self.fd)),
+            ("orthographic", _) => Ok(camera::init_orthographic(
+                lf,
+                la,
+                vup,
+                self.ortho_height,
+                ar,
+            )),
+            ("fisheye", _) => Ok(camera::init_fisheye(
+                lf,
+                la,
+                vup,
+                self.fisheye_fov,
+                self.fisheye_mapping,
+                ar,
+            )),
+            ("equirectangular", _) => Ok(camera::init_equirectangular(lf, la, vup)),
+            (p, _) => Err(format!("unknown projection: {p}")),
+        }?;
+        if self.iso.is_some() || self.shutter.is_some() {
+            cam.ev = camera::exposure_ev(
+                self.f_number,
+                self.shutter.unwrap_or(0.01),
+                self.iso.unwrap_or(100.0),
+            );
+        }
+        if self.shift_x != 0.0 || self.shift_y != 0.0 {
+            match cam.projection {
+                camera::Projection::Perspective | camera::Projection::Orthographic => {
+                    cam.shift(self.shift_x, self.shift_y)
+                }
+                _ => {
+                    return Err(String::from(
+                        "lens shift needs a perspective or orthographic camera",
+                    ))
+                }
+            }
+        }
+        cam.tilt = self.tilt.to_radians();
+        cam.swing = self.swing.to_radians();
+        if let Some(path) = &self.lens {
+            if cam.projection != camera::Projection::Perspective
+                || self.stereo != camera::StereoLayout::Mono
+            {
+                return Err(String::from("lens systems need a mono perspective camera"));
+            }
+            if cam.shift_x != 0.0 || cam.shift_y != 0.0 || cam.tilt != 0.0 || cam.swing != 0.0 {
+                return Err(String::from("lens systems can't shift or tilt"));
+            }
+            let elements = lens::load(path)?;
+            cam.lens = Some(lens::LensSystem::new(
+                elements,
+                self.sensor_width,
+                self.sensor_width / ar,
+                cam.fd * 1000.0,
+            )?);
+        }
+        if self.autofocus {
+            if let Err(e) = cam.autofocus(world) {
+                warnings.push(e);
+            }
+        }
+        let convergence = match (self.convergence, cam.projection) {
+            (Some(c), _) => c,
+            (None, camera::Projection::Equirectangular) => 0.0,
+            (None, _) => cam.fd,
+        };
+        if let Some(path) = &self.aperture_mask {
+            let (w, h, pixels) = util::read_pnm(path).map_err(|e| e.to_string())?;
+            let mask = camera::ApertureMask::new(w, h, &pixels)
+                .ok_or(format!("{path}: aperture mask is empty"))?;
+            cam.aperture = camera::Aperture::Mask(mask);
+        } else if self.blades >= 3 {
+            cam.aperture = camera::Aperture::Polygon {
+                blades: self.blades,
+                rotation: self.blade_rotation.to_radians(),
+            };
+        }
+        cam.stereo = camera::Stereo {
+            layout: self.stereo,
+            iod: self.iod,
+            convergence,
+        };
+        Ok((cam, warnings))
+    }
+}
+
+#[derive(Default)]
+pub struct Scene {
+    pub camera: CameraDesc,
+    pub world: HittableList,
+    pub animation: animation::Animation,
+}
+
+impl Scene {
+    // Camera and objects posed for the given frame.
+    pub fn at_frame(&self, frame: f32) -> (CameraDesc, HittableList) {
+        let mut cam = self.camera.clone();
+        let mut world = self.world.clone();
+        self.animation.apply(frame, &mut cam, &mut world);
+        (cam, world)
+    }
+}
+
+// Fingerprint of a posed camera and its objects, for telling whether a
+// checkpoint was rendered from the same scene. Custom shapes and materials
+// only contribute their bounds and albedo.
+pub fn fingerprint(camera: &CameraDesc, world: &HittableList) -> u64 {
+    let mut h: util::Fingerprint = Default::default();
+    let vec = |h: &mut util::Fingerprint, v: &Vec| {
+        for c in [v.x, v.y, v.z] {
+            h.add_f32(c);
+        }
+    };
+    let c = camera;
+    h.add_str(&c.projection);
+    for v in [&c.lf, &c.la, &c.vup] {
+        vec(&mut h, v);
+    }
+    for v in [
+        c.vfov,
+        c.ap,
+        c.fd,
+        c.focal_length.unwrap_or(-1.0),
+        c.f_number,
+        c.sensor_width,
+        c.iso.unwrap_or(-1.0),
+        c.shutter.unwrap_or(-1.0),
+        c.shift_x,
+        c.shift_y,
+        c.tilt,
+        c.swing,
+        c.blade_rotation,
+        c.ortho_height,
+        c.fisheye_fov,
+        c.iod,
+        c.convergence.unwrap_or(-1.0),
+    ] {
+        h.add_f32(v);
+    }
+    for s in [&c.lens, &c.aperture_mask] {
+        h.add_str(s.as_deref().unwrap_or(""));
+    }
+    h.add(c.autofocus as u64);
+    h.add(c.blades as u64);
+    h.add(c.fisheye_mapping as u64);
+    h.add(c.stereo as u64);
+
+    h.add(world.objects.len() as u64);
+    for obj in &world.objects {
+        let b = obj.bounding_box();
+        vec(&mut h, &b.min);
+        vec(&mut h, &b.max);
+        match obj.material() {
+            Materials::MaterialNone => h.add(0),
+            Materials::Dielectric(d) => {
+                h.add(1);
+                h.add_f32(d.ir);
+            }
+            Materials::Lambertian(l) => {
+                h.add(2);
+                vec(&mut h, &l.albedo);
+            }
+            Materials::Metal(m) => {
+                h.add(3);
+                vec(&mut h, &m.albedo);
+                h.add_f32(m.fuzz);
+            }
+            Materials::Custom(m) => {
+                h.add(4);
+                vec(&mut h, &m.albedo());
+            }
+        }
+    }
+    h.finish()
+}
+
+// The book cover scene: a ground plane, three large spheres and a grid of
+// small random ones. Used when a scene file gives no objects.
+pub fn random(rng: &mut util::Rng) -> HittableList {
+    let mut world: HittableList = Default::default();
+
+    let mat_gnd = Materials::Lambertian(Lambertian {
+        albedo: Colour {
+            x: 0.5,
+            y: 0.5,
+            z: 0.5,
+        },
+    });
+    world.objects.push(Hittables::Sphere(Sphere {
+        c: Point {
+            y: -1000.0,
+            ..Default::default()
+        },
+        r: 1000.0,
+        mat: mat_gnd,
+    }));
+
+    for i in -11..11 {
+        for j in -11..11 {
+            let choose = util::rand_f32(rng);
+            let centre = Point {
+                x: i as f32 + 0.9 * util::rand_f32(rng),
+                y: 0.2,
+                z: j as f32 + 0.9 * util::rand_f32(rng),
+            };
+            if (centre.sub(&[Point {
+                x: 4.0,
+                y: 0.2,
+                ..Default::default()
+            }]))
+            .len()
+                > 0.9
+            {
+                let mat = if choose < 0.8 {
+                    let albedo = vec::rand(rng).mul(&[vec::rand(rng)]);
+                    Materials::Lambertian(Lambertian { albedo })
+                } else if choose < 0.96 {
+                    let albedo = vec::randmm(rng, 0.5, 1.0);
+                    let fuzz = util::randmm_f32(rng, 0.0, 0.5);
+                    Materials::Metal(Metal { albedo, fuzz })
+                } else {
+                    Materials::Dielectric(Dielectric { ir: 1.5 })
+                };
+                world.objects.push(Hittables::Sphere(Sphere {
+                    c: centre,
+                    r: 0.2,
+                    mat,
+                }));
+            }
+        }
+    }
+
+    let mut mat = Materials::Dielectric(Dielectric { ir: 1.5 });
+    world.objects.push(Hittables::Sphere(Sphere {
+        c: Point {
+            y: 1.0,
+            ..Default::default()
+        },
+        r: 1.0,
+        mat,
+    }));
+
+    mat = Materials::Lambertian(Lambertian {
+        albedo: Colour {
+            x: 0.4,
+            y: 0.2,
+            z: 0.1,
+        },
+    });
+    world.objects.push(Hittables::Sphere(Sphere {
+        c: Point {
+            x: -4.0,
+            y: 1.0,
+            ..Default::default()
+        },
+        r: 1.0,
+        mat,
+    }));
+
+    mat = Materials::Metal(Metal {
+        albedo: Colour {
+            x: 0.7,
+            y: 0.6,
+            z: 0.5,
+        },
+        ..Default::default()
+    });
+    world.objects.push(Hittables::Sphere(Sphere {
+        c: Point {
+            x: 4.0,
+            y: 1.0,
+            ..Default::default()
+        },
+        r: 1.0,
+        mat,
+    }));
+
+    world
+}
+
+fn floats<const N: usize>(args: &[&str]) -> Result<[f32; N], String> {
+    if args.len() < N {
+        return Err(format!("expected {N} numbers"));
+    }
+    let mut out = [0.0; N];
+    for (o, a) in out.iter_mut().zip(args) {
+        *o = a.parse().map_err(|_| format!("invalid number: {a}"))?;
+    }
+    Ok(out)
+}
+
+fn vec3(args: &[&str]) -> Result<Vec, String> {
+    let [x, y, z] = floats(args)?;
+    Ok(Vec { x, y, z })
+}
+
+pub fn stereo_layout(name: &str) -> Result<camera::StereoLayout, String> {
+    match name {
+        "mono" => Ok(camera::StereoLayout::Mono),
+        "sbs" => Ok(camera::StereoLayout::SideBySide),
+        "tb" => Ok(camera::StereoLayout::TopBottom),
+        _ => Err(format!("unknown stereo layout: {name}")),
+    }
+}
+
+fn material(args: &[&str]) -> Result<Materials, String> {
+    match args.first() {
+        Some(&"lambertian") => Ok(Materials::Lambertian(Lambertian {
+            albedo: vec3(&args[1..])?,
+        })),
+        Some(&"metal") => {
+            let [x, y, z, fuzz] = floats(&args[1..])?;
+            Ok(Materials::Metal(Metal {
+                albedo: Colour { x, y, z },
+                fuzz,
+            }))
+        }
+        Some(&"dielectric") => {
+            let [ir] = floats(&args[1..])?;
+            Ok(Materials::Dielectric(Dielectric { ir }))
+        }
+        Some(m) => Err(format!("unknown material: {m}")),
+        None => Err(String::from("missing material")),
+    }
+}
+
+fn animation_key(anim: &mut animation::Animation, args: &[&str]) -> Result<(), String> {
+    let [frame] = floats(args)?;
+    let args = &args[1..];
+    match args.first() {
+        Some(&"lookfrom") => floats(&args[1..]).map(|v| anim.lookfrom.insert(frame, v)),
+        Some(&"lookat") => floats(&args[1..]).map(|v| anim.lookat.insert(frame, v)),
+        Some(&"vfov") => floats(&args[1..]).map(|v| anim.vfov.insert(frame, v)),
+        Some(&"focus_dist") => floats(&args[1..]).map(|v| anim.focus_dist.insert(frame, v)),
+        Some(&"translate") => {
+            let [i, x, y, z] = floats(&args[1..])?;
+            let i = i as usize;
+            match anim.translate.iter_mut().find(|(j, _)| *j == i) {
+                Some((_, track)) => track.insert(frame, [x, y, z]),
+                None => {
+                    let mut track: animation::Track<3> = Default::default();
+                    track.insert(frame, [x, y, z]);
+                    anim.translate.push((i, track));
+                }
+            }
+            Ok(())
+        }
+        Some(p) => Err(format!("can't animate {p}")),
+        None => Err(String::from("missing property")),
+    }
+}
+
+// Parses a scene description. Each line is a keyword followed by its
+// arguments; '#' starts a comment. Keys animate the camera, and move
+// objects, numbered from 0 in the order they are declared, by an offset.
+//
+//   camera perspective|orthographic|fisheye|equirectangular
+//   lookfrom x y z            lookat x y z            vup x y z
+//   vfov degrees              aperture diameter       focus_dist d
+//   focal_length mm           f_number n              sensor_width mm
+//   iso n                     shutter seconds         autofocus
+//   shift x y                 tilt degrees            swing degrees
+//   lens file.lens
+//   aperture_blades n         aperture_rotation degrees
+//   aperture_mask image.pgm
+//   ortho_height h            fisheye_fov degrees
+//   fisheye_mapping equidistant|equisolid
+//   stereo mono|sbs|tb        iod distance            convergence distance
+//   frames first last         interpolation linear|bezier
+//   key frame lookfrom|lookat x y z
+//   key frame vfov|focus_dist v
+//   key frame translate object dx dy dz
+//   sphere cx cy cz radius lambertian r g b
+//   sphere cx cy cz radius metal r g b fuzz
+//   sphere cx cy cz radius dielectric ir
+pub fn parse(src: &str) -> Result<Scene, String> {
+    let mut scene: Scene = Default::default();
+    for (n, line) in src.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let words: std::vec::Vec<&str> = line.split_whitespace().collect();
+        let Some((&key, args)) = words.split_first() else {
+            continue;
+        };
+        let cam = &mut scene.camera;
+        let res = match key {
+            "camera" => match args.first() {
+                Some(p) => {
+                    cam.projection = p.to_string();
+                    Ok(())
+                }
+                None => Err(String::from("missing projection")),
+            },
+            "lookfrom" => vec3(args).map(|v| cam.lf = v),
+            "lookat" => vec3(args).map(|v| cam.la = v),
+            "vup" => vec3(args).map(|v| cam.vup = v),
+            "vfov" => floats(args).map(|[v]| cam.vfov = v),
+            "aperture" => floats(args).map(|[v]| cam.ap = v),
+            "focus_dist" => floats(args).map(|[v]| cam.fd = v),
+            "focal_length" => floats(args).map(|[v]| cam.focal_length = Some(v)),
+            "f_number" => floats(args).map(|[v]| cam.f_number = v),
+            "sensor_width" => floats(args).map(|[v]| cam.sensor_width = v),
+            "iso" => floats(args).map(|[v]| cam.iso = Some(v)),
+            "shutter" => floats(args).map(|[v]| cam.shutter = Some(v)),
+            "autofocus" => {
+                cam.autofocus = true;
+                Ok(())
+            }
+            "shift" => floats(args).map(|[x, y]| {
+                cam.shift_x = x;
+                cam.shift_y = y;
+            }),
+            "tilt" => floats(args).map(|[v]| cam.tilt = v),
+            "swing" => floats(args).map(|[v]| cam.swing = v),
+            "lens" => match args.first() {
+                Some(path) => {
+                    cam.lens = Some(path.to_string());
+                    Ok(())
+                }
+                None => Err(String::from("missing lens path")),
+            },
+            "aperture_blades" => floats(args).map(|[v]| cam.blades = v as u32),
+            "aperture_rotation" => floats(args).map(|[v]| cam.blade_rotation = v),
+            "aperture_mask" => match args.first() {
+                Some(path) => {
+                    cam.aperture_mask = Some(path.to_string());
+                    Ok(())
+                }
+                None => Err(String::from("missing image path")),
+            },
+            "ortho_height" => floats(args).map(|[v]| cam.ortho_height = v),
+            "fisheye_fov" => floats(args).map(|[v]| cam.fisheye_fov = v),
+            "fisheye_mapping" => match args.first() {
+                Some(&"equidistant") => {
+                    cam.fisheye_mapping = camera::FisheyeMapping::Equidistant;
+                    Ok(())
+                }
+                Some(&"equisolid") => {
+                    cam.fisheye_mapping = camera::FisheyeMapping::Equisolid;
+                    Ok(())
+                }
+                _ => Err(String::from("expected equidistant or equisolid")),
+            },
+            "stereo" => args
+                .first()
+                .ok_or(String::from("missing stereo layout"))
+                .and_then(|l| stereo_layout(l))
+                .map(|l| cam.stereo = l),
+            "iod" => floats(args).map(|[v]| cam.iod = v),
+            "convergence" => floats(args).map(|[v]| cam.convergence = Some(v)),
+            "frames" => floats(args).map(|[a, b]| {
+                scene.animation.frames = Some((a as i32, b as i32));
+            }),
+            "interpolation" => match args.first() {
+                Some(&"linear") => {
+                    scene.animation.interpolation = animation::Interpolation::Linear;
+                    Ok(())
+                }
+                Some(&"bezier") => {
+                    scene.animation.interpolation = animation::Interpolation::Bezier;
+                    Ok(())
+                }
+                _ => Err(String::from("expected linear or bezier")),
+            },
+            "key" => animation_key(&mut scene.animation, args),
+            "sphere" => floats(args).and_then(|[x, y, z, r]| {
+                let mat = material(&args[4..])?;
+                scene.world.objects.push(Hittables::Sphere(Sphere {
+                    c: Point { x, y, z },
+                    r,
+                    mat,
+                }));
+                Ok(())
+            }),
+            _ => Err(format!("unknown keyword: {key}")),
+        };
+        res.map_err(|e| format!("line {}: {e}", n + 1))?;
+    }
+    if let Some((i, _)) = scene
+        .animation
+        .translate
+        .iter()
+        .find(|(i, _)| *i >= scene.world.objects.len())
+    {
+        return Err(format!("key translate: there is no object {i}"));
+    }
+    Ok(scene)
+}
+
+pub fn load(path: &str) -> Result<Scene, String> {
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    parse(&src).map_err(|e| format!("{path}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittables;
+    #[test]
+    fn parse_scene() {
+        let s = parse(
+            "# test scene
+            camera fisheye
+            lookfrom 0 1 2
+            fisheye_fov 200   # wider than a hemisphere
+            sphere 0 0 -1 0.5 metal 0.8 0.8 0.8 0.1
+            sphere 0 -100 -1 100 lambertian 0.5 0.5 0.5",
+        )
+        .unwrap();
+        assert_eq!(s.camera.projection, "fisheye");
+        assert_eq!(s.camera.lf.z, 2.0);
+        assert_eq!(s.camera.fisheye_fov, 200.0);
+        assert_eq!(s.world.objects.len(), 2);
+
+        assert!(parse("sphere 0 0 0").is_err());
+        assert!(parse("sphere 0 0 0 1 plastic").is_err());
+        assert!(parse("lookat 1 2").is_err());
+
+        let s = parse(
+            "sphere 0 0 0 1 dielectric 1.5
+            key 1 lookfrom 0 0 10
+            key 11 lookfrom 10 0 10
+            key 11 translate 0 0 2 0",
+        )
+        .unwrap();
+        assert_eq!(s.animation.frame_range(), Some((1, 11)));
+        let (cam, world) = s.at_frame(6.0);
+        assert_eq!(cam.lf.x, 5.0);
+        let Hittables::Sphere(sphere) = &world.objects[0] else {
+            panic!("not a sphere");
+        };
+        assert_eq!(sphere.c.y, 2.0);
+        assert!(parse("key 1 translate 3 0 0 0").is_err());
+        assert!(parse("key 1 vup 0 1 0").is_err());
+    }
+
+    #[test]
+    fn fingerprints() {
+        let src = "lookfrom 0 1 4
+            sphere 0 0.5 0 0.5 metal 0.8 0.8 0.8 0.2
+            sphere 0 -100 0 100 lambertian 0.5 0.5 0.5";
+        let fp = |src: &str| {
+            let s = parse(src).unwrap();
+            fingerprint(&s.camera, &s.world)
+        };
+        let base = fp(src);
+        assert_eq!(base, fp(src));
+        for other in [
+            src.replace("lookfrom 0 1 4", "lookfrom 0 1 5"),
+            src.replace("0 0.5 0 0.5", "0 0.6 0 0.5"),
+            src.replace("0.2", "0.3"),
+            src.replace("lambertian", "metal 0.5 0.5 0.5 0 # "),
+            format!("{src}\ncamera orthographic"),
+        ] {
+            assert_ne!(base, fp(&other), "{other}");
+        }
+    }
+}
@@ -0,0 +1,344 @@
+pub const USAGE: &str = "usage: ray-tracer-rust [options]
+  --width <px>          image width (default 400)
+  --aspect <ratio>      image width / height (default 1.5)
+  --scene <file>        scene description; without objects the random scene is used
+  --projection <name>   perspective, orthographic, fisheye or equirectangular,
+                        overriding the scene's camera
+  --focal-length <mm>   build the camera from a focal length, f-number and
+                        sensor width instead of a field of view
+  --f-number <n>        lens f-number (default 8)
+  --sensor-width <mm>   sensor width (default 36)
+  --iso <n>             sensor ISO; with --shutter sets the exposure
+  --shutter <s>         shutter time in seconds
+  --autofocus           focus on the object at the centre of the image
+  --shift-x <f>         shift the frame right by a fraction of its width
+  --shift-y <f>         shift the frame up by a fraction of its height
+  --tilt <deg>          tilt the focal plane, moving its top away
+  --swing <deg>         swing the focal plane, moving its right side away
+  --lens <file>         trace through a lens system described in file, with the
+                        film as wide as --sensor-width
+  --aperture-blades <n> polygonal bokeh with n aperture blades
+  --aperture-rotation <deg>
+                        rotation of the aperture polygon
+  --aperture-mask <img> bokeh shape from a greyscale PGM/PPM image
+  --stereo <layout>     mono, sbs (side by side) or tb (top/bottom); --width and
+                        --aspect then describe a single eye
+  --iod <d>             interocular distance in scene units (default 0.064)
+  --convergence <d>     zero parallax distance, 0 for parallel eyes
+  --first-frame <n>     first frame of an animated scene; frames are written
+                        to numbered files such as img_0001.ppm
+  --last-frame <n>      last frame of an animated scene
+  --region <x0,y0,x1,y1>
+                        only render pixels x0..x1 of rows y0..y1, counted from
+                        the top left; the rest of the image is black
+  --crop                write just the region instead of the full image
+  --aov <names>         also write first hit data and light passes to PFM files
+                        named after the output: depth, normal, albedo,
+                        position, object, material, motion, emission,
+                        diffuse_direct, diffuse_indirect, specular and
+                        transmission, comma separated, or all
+  --denoise             denoise the final image, guided by normals and albedo
+  --denoise-iterations <n>
+                        denoiser passes, each reaching twice as far (default 5)
+  --samples <n>         samples per pixel, the maximum when adaptive (default 100)
+  --adaptive <err>      keep sampling pixels until their relative error is below err
+  --min-samples <n>     samples per adaptive pass (default 16)
+  --spp-map <file>      also write a greyscale map of samples per pixel
+  --pass-samples <n>    samples per pixel per progressive pass; the image is
+                        rewritten after every pass
+  --checkpoint <file>   save the accumulated samples to file during the render
+  --checkpoint-interval <s>
+                        minimum seconds between checkpoints (default 0)
+  --resume <file>       continue from a checkpoint made with the same seed,
+                        sampler and filter; raise --samples to add more. Not
+                        available with --aov or --denoise
+  --filter <name>       box, tent, gaussian, mitchell or lanczos (default box)
+  --filter-radius <px>  filter radius, defaults to the filter's usual size
+  --integrator <name>   path, or for diagnosing scenes normals, uv, depth, ao,
+                        albedo, bounces (heatmap) or cost (heatmap of
+                        intersection tests) (default path)
+  --ao-distance <d>     how far ao looks for occluders (default 1)
+  --max-depth <n>       maximum path length (default 50)
+  --rr-depth <n>        bounces before russian roulette starts (default 3)
+  --seed <n>            random seed (default 0)
+  --sampler <name>      independent, stratified, halton or sobol (default sobol)
+  --output <file>       output image (default img.ppm)
+  --stats-json <file>   also write the render statistics to file as JSON";
+
+#[derive(Clone)]
+pub struct Settings {
+    pub aspect_ratio: f32,
+    pub image_width: i32,
+    pub first_frame: Option<i32>,
+    pub last_frame: Option<i32>,
+    pub region: Option<crate::render::Region>,
+    pub crop: bool,
+    pub aov: Option<String>,
+    pub denoise: bool,
+    pub denoise_iterations: u32,
+    pub n_samples: i32,
+    pub adaptive_threshold: f32,
+    pub min_samples: i32,
+    pub spp_map: Option<String>,
+    pub pass_samples: i32,
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: f32,
+    pub resume: Option<String>,
+    pub filter: String,
+    pub filter_radius: Option<f32>,
+    pub integrator: crate::integrator::Integrator,
+    pub ao_distance: f32,
+    pub max_depth: i32,
+    pub rr_depth: i32,
+    pub seed: u64,
+    pub sampler: String,
+    pub scene: Option<String>,
+    pub projection: Option<String>,
+    pub focal_length: Option<f32>,
+    pub f_number: Option<f32>,
+    pub sensor_width: Option<f32>,
+    pub iso: Option<f32>,
+    pub shutter: Option<f32>,
+    pub autofocus: bool,
+    pub shift_x: Option<f32>,
+    pub shift_y: Option<f32>,
+    pub tilt: Option<f32>,
+    pub swing: Option<f32>,
+    pub lens: Option<String>,
+    pub aperture_blades: Option<u32>,
+    pub aperture_rotation: Option<f32>,
+    pub aperture_mask: Option<String>,
+    pub stereo: Option<String>,
+    pub iod: Option<f32>,
+    pub convergence: Option<f32>,
+    pub output: String,
+    pub stats_json: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            aspect_ratio: 3.0 / 2.0,
+            image_width: 400,
+            first_frame: None,
+            last_frame: None,
+            region: None,
+            crop: false,
+            aov: None,
+            denoise: false,
+            denoise_iterations: 5,
+            n_samples: 100,
+            adaptive_threshold: 0.0,
+            min_samples: 16,
+            spp_map: None,
+            pass_samples: 0,
+            checkpoint: None,
+            checkpoint_interval: 0.0,
+            resume: None,
+            filter: String::from("box"),
+            filter_radius: None,
+            integrator: Default::default(),
+            ao_distance: 1.0,
+            max_depth: 50,
+            rr_depth: 3,
+            seed: 0,
+            sampler: String::from("sobol"),
+            scene: None,
+            projection: None,
+            focal_length: None,
+            f_number: None,
+            sensor_width: None,
+            iso: None,
+            shutter: None,
+            autofocus: false,
+            shift_x: None,
+            shift_y: None,
+            tilt: None,
+            swing: None,
+            lens: None,
+            aperture_blades: None,
+            aperture_rotation: None,
+            aperture_mask: None,
+            stereo: None,
+            iod: None,
+            convergence: None,
+            output: String::from("img.ppm"),
+            stats_json: None,
+        }
+    }
+}
+
+impl Settings {
+    pub fn image_height(&self) -> i32 {
+        (self.image_width as f32 / self.aspect_ratio) as i32
+    }
+
+    // Checks the image size and sample count, which nothing downstream can
+    // recover from.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.image_width <= 0 {
+            return Err(format!(
+                "image width must be positive: {}",
+                self.image_width
+            ));
+        }
+        if !(self.aspect_ratio.is_finite() && self.aspect_ratio > 0.0) {
+            return Err(format!(
+                "aspect ratio must be positive: {}",
+                self.aspect_ratio
+            ));
+        }
+        let height = self.image_height();
+        if height <= 0 {
+            return Err(format!(
+                "image height is zero: width {} at aspect ratio {}",
+                self.image_width, self.aspect_ratio
+            ));
+        }
+        // A stereo film doubles one side, and pixels are indexed with i32.
+        if 2 * self.image_width as i64 * height as i64 > i32::MAX as i64 {
+            return Err(format!("image too large: {}x{height}", self.image_width));
+        }
+        if self.n_samples <= 0 {
+            return Err(format!(
+                "samples per pixel must be positive: {}",
+                self.n_samples
+            ));
+        }
+        Ok(())
+    }
+
+    // Each frame of an animation gets its own noise; a still keeps the
+    // plain seed. Whole frames hash as integers, so frame 2 of the command
+    // line and frame 2.0 of Renderer render the same noise.
+    pub fn frame_seed(&self, frame: Option<f32>) -> u64 {
+        match frame {
+            Some(f) if f.fract() == 0.0 => crate::util::splitmix64(self.seed ^ f as i64 as u64),
+            Some(f) => crate::util::splitmix64(self.seed ^ f.to_bits() as u64),
+            None => self.seed,
+        }
+    }
+
+    // Overrides the scene's camera with the camera options that were given.
+    pub fn apply_camera(&self, cam: &mut crate::scene::CameraDesc) -> Result<(), String> {
+        if let Some(p) = &self.projection {
+            cam.projection = p.clone();
+        }
+        if self.focal_length.is_some() {
+            cam.focal_length = self.focal_length;
+        }
+        if let Some(n) = self.f_number {
+            cam.f_number = n;
+        }
+        if let Some(w) = self.sensor_width {
+            cam.sensor_width = w;
+        }
+        if self.iso.is_some() {
+            cam.iso = self.iso;
+        }
+        if self.shutter.is_some() {
+            cam.shutter = self.shutter;
+        }
+        cam.autofocus |= self.autofocus;
+        if let Some(x) = self.shift_x {
+            cam.shift_x = x;
+        }
+        if let Some(y) = self.shift_y {
+            cam.shift_y = y;
+        }
+        if let Some(t) = self.tilt {
+            cam.tilt = t;
+        }
+        if let Some(s) = self.swing {
+            cam.swing = s;
+        }
+        if self.lens.is_some() {
+            cam.lens = self.lens.clone();
+        }
+        if let Some(n) = self.aperture_blades {
+            cam.blades = n;
+        }
+        if let Some(r) = self.aperture_rotation {
+            cam.blade_rotation = r;
+        }
+        if let Some(path) = &self.aperture_mask {
+            cam.aperture_mask = Some(path.clone());
+        }
+        if let Some(layout) = &self.stereo {
+            cam.stereo = crate::scene::stereo_layout(layout)?;
+        }
+        if let Some(iod) = self.iod {
+            cam.iod = iod;
+        }
+        if let Some(c) = self.convergence {
+            cam.convergence = Some(c);
+        }
+        Ok(())
+    }
+}
+
+fn value<T: std::str::FromStr>(
+    flag: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<T, String> {
+    let v = args.next().ok_or(format!("{flag} expects a value"))?;
+    v.parse()
+        .map_err(|_| format!("invalid value for {flag}: {v}"))
+}
+
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Settings, String> {
+    let mut s: Settings = Default::default();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--width" => s.image_width = value(&flag, &mut args)?,
+            "--aspect" => s.aspect_ratio = value(&flag, &mut args)?,
+            "--scene" => s.scene = Some(value(&flag, &mut args)?),
+            "--projection" => s.projection = Some(value(&flag, &mut args)?),
+            "--focal-length" => s.focal_length = Some(value(&flag, &mut args)?),
+            "--f-number" => s.f_number = Some(value(&flag, &mut args)?),
+            "--sensor-width" => s.sensor_width = Some(value(&flag, &mut args)?),
+            "--iso" => s.iso = Some(value(&flag, &mut args)?),
+            "--shutter" => s.shutter = Some(value(&flag, &mut args)?),
+            "--autofocus" => s.autofocus = true,
+            "--shift-x" => s.shift_x = Some(value(&flag, &mut args)?),
+            "--shift-y" => s.shift_y = Some(value(&flag, &mut args)?),
+            "--tilt" => s.tilt = Some(value(&flag, &mut args)?),
+            "--swing" => s.swing = Some(value(&flag, &mut args)?),
+            "--lens" => s.lens = Some(value(&flag, &mut args)?),
+            "--aperture-blades" => s.aperture_blades = Some(value(&flag, &mut args)?),
+            "--aperture-rotation" => s.aperture_rotation = Some(value(&flag, &mut args)?),
+            "--aperture-mask" => s.aperture_mask = Some(value(&flag, &mut args)?),
+            "--stereo" => s.stereo = Some(value(&flag, &mut args)?),
+            "--iod" => s.iod = Some(value(&flag, &mut args)?),
+            "--convergence" => s.convergence = Some(value(&flag, &mut args)?),
+            "--first-frame" => s.first_frame = Some(value(&flag, &mut args)?),
+            "--last-frame" => s.last_frame = Some(value(&flag, &mut args)?),
+            "--region" => s.region = Some(value(&flag, &mut args)?),
+            "--crop" => s.crop = true,
+            "--aov" => s.aov = Some(value(&flag, &mut args)?),
+            "--denoise" => s.denoise = true,
+            "--denoise-iterations" => s.denoise_iterations = value(&flag, &mut args)?,
+            "--samples" => s.n_samples = value(&flag, &mut args)?,
+            "--adaptive" => s.adaptive_threshold = value(&flag, &mut args)?,
+            "--min-samples" => s.min_samples = value(&flag, &mut args)?,
+            "--spp-map" => s.spp_map = Some(value(&flag, &mut args)?),
+            "--pass-samples" => s.pass_samples = value(&flag, &mut args)?,
+            "--checkpoint" => s.checkpoint = Some(value(&flag, &mut args)?),
+            "--checkpoint-interval" => s.checkpoint_interval = value(&flag, &mut args)?,
+            "--resume" => s.resume = Some(value(&flag, &mut args)?),
+            "--filter" => s.filter = value(&flag, &mut args)?,
+            "--filter-radius" => s.filter_radius = Some(value(&flag, &mut args)?),
+            "--integrator" => s.integrator = value(&flag, &mut args)?,
+            "--ao-distance" => s.ao_distance = value(&flag, &mut args)?,
+            "--max-depth" => s.max_depth = value(&flag, &mut args)?,
+            "--rr-depth" => s.rr_depth = value(&flag, &mut args)?,
+            "--seed" => s.seed = value(&flag, &mut args)?,
+            "--sampler" => s.sampler = value(&flag, &mut args)?,
+            "--output" => s.output = value(&flag, &mut args)?,
+            "--stats-json" => s.stats_json = Some(value(&flag, &mut args)?),
+            "--help" | "-h" => return Err(String::from(USAGE)),
+            _ => return Err(format!("unknown option: {flag}\n{USAGE}")),
+        }
+    }
+    Ok(s)
+}
@@ -1,49 +1,63 @@
-pub mod sphere {
-    use crate::hittable;
-    use crate::material;
-    use crate::ray;
-    use crate::vec;
-
-    pub struct Sphere {
-        pub c: vec::vec::Point,
-        pub r: f32,
-        pub mat: material::material::Materials,
-    }
+use crate::bvh;
+use crate::hittable;
+use crate::material;
+use crate::ray;
+use crate::vec;
 
-    impl hittable::hittable::Hittable for Sphere {
-        fn hit(
-            &self,
-            r: &ray::ray::Ray,
-            t_min: f32,
-            t_max: f32,
-            rec: &mut hittable::hittable::HitRecord,
-        ) -> bool {
-            let oc = r.orig.sub(&[self.c]);
-            let a = r.dir.len_sqrd();
-            let b = oc.dot(&r.dir);
-            let c = oc.len_sqrd() - self.r * self.r;
-
-            let discriminant = b * b - a * c;
-            if discriminant < 0.0 {
-                return false;
-            }
-            let sqrtd = discriminant.sqrt();
+#[derive(Clone)]
+pub struct Sphere {
+    pub c: vec::Point,
+    pub r: f32,
+    pub mat: material::Materials,
+}
+
+impl hittable::Hittable for Sphere {
+    fn hit(&self, r: &ray::Ray, t_min: f32, t_max: f32, rec: &mut hittable::HitRecord) -> bool {
+        let oc = r.orig.sub(&[self.c]);
+        let a = r.dir.len_sqrd();
+        let b = oc.dot(&r.dir);
+        let c = oc.len_sqrd() - self.r * self.r;
+
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 {
+            return false;
+        }
+        let sqrtd = discriminant.sqrt();
 
-            let mut root = (-b - sqrtd) / a;
+        let mut root = (-b - sqrtd) / a;
+        if root < t_min || root > t_max {
+            root = (-b + sqrtd) / a;
             if root < t_min || root > t_max {
-                root = (-b + sqrtd) / a;
-                if root < t_min || root > t_max {
-                    return false;
-                }
+                return false;
             }
+        }
+
+        rec.t = root;
+        rec.p = r.at(rec.t);
+        let out_n = rec.p.sub(&[self.c]).divf(self.r);
+        rec.set_face_norm(r, &out_n);
+        rec.mat = self.mat.clone();
+        // Longitude from -x round through +z, latitude from the south pole.
+        let pi = std::f32::consts::PI;
+        rec.u = ((-out_n.z).atan2(out_n.x) + pi) / (2.0 * pi);
+        rec.v = (-out_n.y).clamp(-1.0, 1.0).acos() / pi;
 
-            rec.t = root;
-            rec.p = r.at(rec.t);
-            let out_n = rec.p.sub(&[self.c]).divf(self.r);
-            rec.set_face_norm(r, &out_n);
-            rec.mat = self.mat;
+        true
+    }
 
-            true
+    fn bounding_box(&self) -> bvh::Aabb {
+        let r = vec::Vec {
+            x: self.r.abs(),
+            y: self.r.abs(),
+            z: self.r.abs(),
+        };
+        bvh::Aabb {
+            min: self.c.sub(&[r]),
+            max: self.c.add(&[r]),
         }
     }
+
+    fn material(&self) -> material::Materials {
+        self.mat.clone()
+    }
 }
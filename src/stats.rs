@@ -0,0 +1,213 @@
+use std::cell::Cell;
+use std::thread::LocalKey;
+use std::time::Duration;
+
+// Counters bumped from the render loop. They are global so the hot paths
+// don't have to thread anything through, but kept per thread: a render runs
+// on the thread that started it, so renders on other threads don't show up
+// in its counts.
+thread_local! {
+    pub static CAMERA_RAYS: Cell<u64> = const { Cell::new(0) };
+    pub static BOUNCE_RAYS: Cell<u64> = const { Cell::new(0) };
+    // Occlusion rays, from the ambient occlusion integrator.
+    pub static SHADOW_RAYS: Cell<u64> = const { Cell::new(0) };
+    // Bounding boxes and objects tested against rays.
+    pub static INTERSECTION_TESTS: Cell<u64> = const { Cell::new(0) };
+    pub static SAMPLES: Cell<u64> = const { Cell::new(0) };
+    // Paths traced and their total number of bounces.
+    pub static PATHS: Cell<u64> = const { Cell::new(0) };
+    pub static PATH_BOUNCES: Cell<u64> = const { Cell::new(0) };
+}
+
+const ALL: [&LocalKey<Cell<u64>>; 7] = [
+    &CAMERA_RAYS,
+    &BOUNCE_RAYS,
+    &SHADOW_RAYS,
+    &INTERSECTION_TESTS,
+    &SAMPLES,
+    &PATHS,
+    &PATH_BOUNCES,
+];
+
+pub fn add(counter: &'static LocalKey<Cell<u64>>, n: u64) {
+    counter.set(counter.get() + n);
+}
+
+// Zeroes this thread's counters, so that the next render counts from zero.
+pub fn reset() {
+    for c in ALL {
+        c.set(0);
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Counters {
+    pub camera_rays: u64,
+    pub bounce_rays: u64,
+    pub shadow_rays: u64,
+    pub intersection_tests: u64,
+    pub samples: u64,
+    pub paths: u64,
+    pub path_bounces: u64,
+}
+
+impl std::ops::AddAssign for Counters {
+    fn add_assign(&mut self, c: Counters) {
+        self.camera_rays += c.camera_rays;
+        self.bounce_rays += c.bounce_rays;
+        self.shadow_rays += c.shadow_rays;
+        self.intersection_tests += c.intersection_tests;
+        self.samples += c.samples;
+        self.paths += c.paths;
+        self.path_bounces += c.path_bounces;
+    }
+}
+
+// This thread's counts since the last reset.
+pub fn counters() -> Counters {
+    let get = |c: &'static LocalKey<Cell<u64>>| c.get();
+    Counters {
+        camera_rays: get(&CAMERA_RAYS),
+        bounce_rays: get(&BOUNCE_RAYS),
+        shadow_rays: get(&SHADOW_RAYS),
+        intersection_tests: get(&INTERSECTION_TESTS),
+        samples: get(&SAMPLES),
+        paths: get(&PATHS),
+        path_bounces: get(&PATH_BOUNCES),
+    }
+}
+
+// Wall clock time spent in each phase, summed over frames.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Timings {
+    pub scene: Duration,
+    pub bvh: Duration,
+    pub render: Duration,
+    pub denoise: Duration,
+    pub output: Duration,
+}
+
+impl std::ops::AddAssign for Timings {
+    fn add_assign(&mut self, t: Timings) {
+        self.scene += t.scene;
+        self.bvh += t.bvh;
+        self.render += t.render;
+        self.denoise += t.denoise;
+        self.output += t.output;
+    }
+}
+
+pub struct Report {
+    pub counters: Counters,
+    pub timings: Timings,
+}
+
+impl Report {
+    pub fn average_path_length(&self) -> f64 {
+        self.counters.path_bounces as f64 / self.counters.paths.max(1) as f64
+    }
+
+    pub fn samples_per_sec(&self) -> f64 {
+        let secs = self.timings.render.as_secs_f64();
+        if secs > 0.0 {
+            self.counters.samples as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let c = &self.counters;
+        let t = &self.timings;
+        format!(
+            "camera rays         {}
+bounce rays         {}
+shadow rays         {}
+intersection tests  {}
+average path length {:.2}
+samples/sec         {:.0}
+scene build         {:.3}s
+bvh build           {:.3}s
+render              {:.3}s
+denoise             {:.3}s
+output              {:.3}s",
+            c.camera_rays,
+            c.bounce_rays,
+            c.shadow_rays,
+            c.intersection_tests,
+            self.average_path_length(),
+            self.samples_per_sec(),
+            t.scene.as_secs_f64(),
+            t.bvh.as_secs_f64(),
+            t.render.as_secs_f64(),
+            t.denoise.as_secs_f64(),
+            t.output.as_secs_f64(),
+        )
+    }
+
+    pub fn json(&self) -> String {
+        let c = &self.counters;
+        let t = &self.timings;
+        format!(
+            "{{
+  \"rays\": {{\"camera\": {}, \"bounce\": {}, \"shadow\": {}}},
+  \"intersection_tests\": {},
+  \"samples\": {},
+  \"average_path_length\": {},
+  \"samples_per_sec\": {},
+  \"seconds\": {{\"scene\": {}, \"bvh\": {}, \"render\": {}, \"denoise\": {}, \"output\": {}}}
+}}
+",
+            c.camera_rays,
+            c.bounce_rays,
+            c.shadow_rays,
+            c.intersection_tests,
+            c.samples,
+            self.average_path_length(),
+            self.samples_per_sec(),
+            t.scene.as_secs_f64(),
+            t.bvh.as_secs_f64(),
+            t.render.as_secs_f64(),
+            t.denoise.as_secs_f64(),
+            t.output.as_secs_f64(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn report() {
+        // Counts are per thread, so renders in other tests don't show up.
+        reset();
+        add(&CAMERA_RAYS, 2);
+        add(&SAMPLES, 3);
+        let c = counters();
+        assert_eq!((c.camera_rays, c.samples, c.paths), (2, 3, 0));
+        reset();
+        assert_eq!(counters(), Counters::default());
+
+        let report = Report {
+            counters: Counters {
+                samples: 300,
+                paths: 4,
+                path_bounces: 10,
+                ..Default::default()
+            },
+            timings: Timings {
+                render: Duration::from_millis(1500),
+                denoise: Duration::from_millis(250),
+                ..Default::default()
+            },
+        };
+        assert_eq!(report.average_path_length(), 2.5);
+        assert_eq!(report.samples_per_sec(), 200.0);
+        let json = report.json();
+        assert!(json.contains("\"samples\": 300,"), "{json}");
+        assert!(json.contains("\"render\": 1.5,"), "{json}");
+        assert!(json.contains("\"denoise\": 0.25,"), "{json}");
+    }
+}
@@ -1,28 +1,229 @@
-pub mod util {
-    use crate::vec;
-    use rand::Rng;
-    use std::fs::File;
-    use std::io::prelude::*;
+use crate::vec;
+use std::fs::File;
+use std::io::prelude::*;
 
-    pub fn rand_f32() -> f32 {
-        rand::thread_rng().gen::<f32>()
+pub fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+// Hash of a sequence of values. Unlike std's DefaultHasher it is the same
+// in every build, so it can be stored in files.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Fingerprint(u64);
+
+impl Fingerprint {
+    pub fn add(&mut self, v: u64) {
+        self.0 = splitmix64(self.0 ^ v);
     }
 
-    pub fn randmm_f32(min: f32, max: f32) -> f32 {
-        min + (max - min) * rand_f32()
+    pub fn add_f32(&mut self, v: f32) {
+        self.add(v.to_bits() as u64);
     }
 
-    pub fn write_colour(f: &mut File, c: &vec::vec::Colour, n_samples: i32) -> std::io::Result<()> {
-        let scale = 1.0 / n_samples as f32;
+    pub fn add_str(&mut self, s: &str) {
+        self.add(s.len() as u64);
+        for b in s.bytes() {
+            self.add(b as u64);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// PCG32 (XSH-RR). Implemented here rather than taken from a crate so that the
+// sequence for a given seed never changes underneath us.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+    inc: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng::with_stream(seed, 0)
+    }
 
-        let r = (c.x * scale).sqrt();
-        let g = (c.y * scale).sqrt();
-        let b = (c.z * scale).sqrt();
+    pub fn with_stream(seed: u64, stream: u64) -> Rng {
+        let mut rng = Rng {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    // Independent generator for one sample of one pixel, so the image does not
+    // depend on the order (or the number of threads) in which pixels are rendered.
+    pub fn for_sample(seed: u64, pixel: u64, sample: u64) -> Rng {
+        Rng::with_stream(splitmix64(seed ^ splitmix64(sample)), pixel)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(6364136223846793005).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        u32_to_f32(self.next_u32())
+    }
+}
+
+// Maps the top 24 bits of x onto [0, 1).
+pub fn u32_to_f32(x: u32) -> f32 {
+    (x >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+pub fn rand_f32(rng: &mut Rng) -> f32 {
+    rng.next_f32()
+}
+
+pub fn randmm_f32(rng: &mut Rng, min: f32, max: f32) -> f32 {
+    min + (max - min) * rand_f32(rng)
+}
+
+// Reads a PGM or PPM image (ASCII or binary) as brightness values in [0, 1],
+// top row first. Colour images are averaged to grey.
+pub fn read_pnm(path: &str) -> std::io::Result<(usize, usize, std::vec::Vec<f32>)> {
+    let data = std::fs::read(path)?;
+    let invalid =
+        |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{path}: {msg}"));
+
+    // Header: magic, width, height and maxval, separated by whitespace and
+    // '#' comments, then a single whitespace byte before binary data.
+    let mut pos = 0;
+    let mut header = std::vec::Vec::new();
+    while header.len() < 4 {
+        while pos < data.len() && (data[pos].is_ascii_whitespace() || data[pos] == b'#') {
+            if data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            } else {
+                pos += 1;
+            }
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(invalid("truncated header"));
+        }
+        header.push(String::from_utf8_lossy(&data[start..pos]).to_string());
+    }
+    pos += 1;
+
+    let channels = match header[0].as_str() {
+        "P2" | "P5" => 1,
+        "P3" | "P6" => 3,
+        _ => return Err(invalid("not a PGM or PPM image")),
+    };
+    let dim = |s: &str| s.parse::<usize>().map_err(|_| invalid("bad header"));
+    let (width, height, maxval) = (dim(&header[1])?, dim(&header[2])?, dim(&header[3])?);
+    if maxval == 0 || maxval > 65535 {
+        return Err(invalid("bad maxval"));
+    }
+    // The size comes from the file, so it may not even fit in memory.
+    let n = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(channels))
+        .ok_or_else(|| invalid("image too large"))?;
+    let bytes = |n: usize| {
+        data.get(pos..)
+            .and_then(|d| d.get(..n))
+            .ok_or_else(|| invalid("truncated data"))
+    };
+
+    let raw: std::vec::Vec<usize> = if header[0] == "P2" || header[0] == "P3" {
+        String::from_utf8_lossy(data.get(pos..).unwrap_or_default())
+            .split_whitespace()
+            .take(n)
+            .map(dim)
+            .collect::<std::io::Result<_>>()?
+    } else if maxval < 256 {
+        bytes(n)?.iter().map(|&b| b as usize).collect()
+    } else {
+        bytes(n.checked_mul(2).ok_or_else(|| invalid("image too large"))?)?
+            .chunks_exact(2)
+            .map(|b| ((b[0] as usize) << 8) | b[1] as usize)
+            .collect()
+    };
+    if raw.len() != n {
+        return Err(invalid("truncated data"));
+    }
+
+    let grey = raw
+        .chunks_exact(channels)
+        .map(|px| px.iter().sum::<usize>() as f32 / (channels * maxval) as f32)
+        .collect();
+    Ok((width, height, grey))
+}
+
+pub fn write_colour(f: &mut File, c: &vec::Colour, n_samples: i32) -> std::io::Result<()> {
+    let scale = 1.0 / n_samples as f32;
+
+    let r = (c.x * scale).sqrt();
+    let g = (c.y * scale).sqrt();
+    let b = (c.z * scale).sqrt();
+
+    let ir = (256.0 * r.clamp(0.0, 0.999)) as i32;
+    let ig = (256.0 * g.clamp(0.0, 0.999)) as i32;
+    let ib = (256.0 * b.clamp(0.0, 0.999)) as i32;
+    f.write_all(format!("{ir} {ig} {ib}\n").as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn rng_is_reproducible() {
+        let mut a = Rng::for_sample(42, 7, 3);
+        let mut b = Rng::for_sample(42, 7, 3);
+        let mut c = Rng::for_sample(42, 7, 4);
+        let xs: Vec<u32> = (0..16).map(|_| a.next_u32()).collect();
+        let ys: Vec<u32> = (0..16).map(|_| b.next_u32()).collect();
+        let zs: Vec<u32> = (0..16).map(|_| c.next_u32()).collect();
+        assert_eq!(xs, ys);
+        assert_ne!(xs, zs);
+
+        for _ in 0..1000 {
+            let f = a.next_f32();
+            assert!((0.0..1.0).contains(&f));
+        }
+    }
 
-        let ir = (256.0 * r.clamp(0.0, 0.999)) as i32;
-        let ig = (256.0 * g.clamp(0.0, 0.999)) as i32;
-        let ib = (256.0 * b.clamp(0.0, 0.999)) as i32;
-        f.write_all(format!("{ir} {ig} {ib}\n").as_bytes())?;
-        Ok(())
+    #[test]
+    fn rejects_bad_images() {
+        let path = std::env::temp_dir().join("rejects_bad_images.pgm");
+        let path = path.to_str().unwrap();
+        let read = |header: &[u8]| {
+            std::fs::write(path, header).unwrap();
+            read_pnm(path).map_err(|e| e.kind())
+        };
+        let (w, h, pixels) = read(b"P5 2 1 255\n\x00\xff").unwrap();
+        assert_eq!((w, h, pixels), (2, 1, vec![0.0, 1.0]));
+        // Sizes whose pixel count overflows, or that the file can't hold.
+        let max = usize::MAX;
+        for header in [
+            format!("P6 {max} 2 255\n"),
+            format!("P5 {} 2 65535\n", max / 2),
+            String::from("P5 100000 100000 255\n"),
+            String::from("P3 2 2 255\n1 2 3"),
+        ] {
+            let r = read(header.as_bytes());
+            assert_eq!(r.err(), Some(std::io::ErrorKind::InvalidData), "{header}");
+        }
+        std::fs::remove_file(path).unwrap();
     }
 }
@@ -1,145 +1,160 @@
-pub mod vec {
-    use crate::util;
-
-    #[derive(Debug, Copy, Clone, PartialEq, Default)]
-    pub struct Vec {
-        pub x: f32,
-        pub y: f32,
-        pub z: f32,
-    }
-
-    impl Vec {
-        pub fn add(&self, others: &[Vec]) -> Vec {
-            let mut v = *self;
-            for other in others {
-                v.x += other.x;
-                v.y += other.y;
-                v.z += other.z;
-            }
-            v
-        }
-
-        pub fn sub(&self, others: &[Vec]) -> Vec {
-            let mut v = *self;
-            for other in others {
-                v.x -= other.x;
-                v.y -= other.y;
-                v.z -= other.z;
-            }
-            v
-        }
+use crate::sampler;
+use crate::util;
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Vec {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
 
-        pub fn mul(&self, others: &[Vec]) -> Vec {
-            let mut v = *self;
-            for other in others {
-                v.x *= other.x;
-                v.y *= other.y;
-                v.z *= other.z;
-            }
-            v
+impl Vec {
+    pub fn add(&self, others: &[Vec]) -> Vec {
+        let mut v = *self;
+        for other in others {
+            v.x += other.x;
+            v.y += other.y;
+            v.z += other.z;
         }
+        v
+    }
 
-        pub fn mulf(&self, val: f32) -> Vec {
-            Vec {
-                x: self.x * val,
-                y: self.y * val,
-                z: self.z * val,
-            }
+    pub fn sub(&self, others: &[Vec]) -> Vec {
+        let mut v = *self;
+        for other in others {
+            v.x -= other.x;
+            v.y -= other.y;
+            v.z -= other.z;
         }
+        v
+    }
 
-        pub fn divf(&self, val: f32) -> Vec {
-            Vec {
-                x: self.x / val,
-                y: self.y / val,
-                z: self.z / val,
-            }
+    pub fn mul(&self, others: &[Vec]) -> Vec {
+        let mut v = *self;
+        for other in others {
+            v.x *= other.x;
+            v.y *= other.y;
+            v.z *= other.z;
         }
+        v
+    }
 
-        pub fn dot(&self, other: &Vec) -> f32 {
-            self.x * other.x + self.y * other.y + self.z * other.z
+    pub fn mulf(&self, val: f32) -> Vec {
+        Vec {
+            x: self.x * val,
+            y: self.y * val,
+            z: self.z * val,
         }
+    }
 
-        pub fn cross(&self, other: &Vec) -> Vec {
-            Vec {
-                x: self.y * other.z - self.z * other.y,
-                y: self.z * other.x - self.x * other.z,
-                z: self.x * other.y - self.y * other.x,
-            }
+    pub fn divf(&self, val: f32) -> Vec {
+        Vec {
+            x: self.x / val,
+            y: self.y / val,
+            z: self.z / val,
         }
+    }
 
-        pub fn unit(&self) -> Vec {
-            self.divf(self.len())
-        }
+    pub fn dot(&self, other: &Vec) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
 
-        pub fn len_sqrd(&self) -> f32 {
-            self.x * self.x + self.y * self.y + self.z * self.z
+    pub fn cross(&self, other: &Vec) -> Vec {
+        Vec {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
         }
+    }
 
-        pub fn len(&self) -> f32 {
-            self.len_sqrd().sqrt()
-        }
+    pub fn unit(&self) -> Vec {
+        self.divf(self.len())
+    }
 
-        pub fn near_zero(&self) -> bool {
-            let s = 1e-8;
-            self.x.abs() < s && self.y.abs() < s && self.z.abs() < s
-        }
+    pub fn len_sqrd(&self) -> f32 {
+        self.x * self.x + self.y * self.y + self.z * self.z
+    }
 
-        pub fn reflect(&self, v: &Vec) -> Vec {
-            self.sub(&[v.mulf(2.0 * self.dot(v))])
-        }
+    pub fn len(&self) -> f32 {
+        self.len_sqrd().sqrt()
+    }
 
-        pub fn refract(&self, n: &Vec, e: f32) -> Vec {
-            let c = self.mulf(-1.0).dot(n).min(1.0);
-            let u = self.add(&[n.mulf(c)]).mulf(e);
-            let v = n.mulf(-1.0 * (1.0 - u.len_sqrd()).abs().sqrt());
-            u.add(&[v])
-        }
+    pub fn near_zero(&self) -> bool {
+        let s = 1e-8;
+        self.x.abs() < s && self.y.abs() < s && self.z.abs() < s
     }
 
-    pub type Point = Vec;
-    pub type Colour = Vec;
+    pub fn reflect(&self, v: &Vec) -> Vec {
+        self.sub(&[v.mulf(2.0 * self.dot(v))])
+    }
 
-    pub fn rand() -> Vec {
-        Vec {
-            x: util::util::rand_f32(),
-            y: util::util::rand_f32(),
-            z: util::util::rand_f32(),
-        }
+    pub fn refract(&self, n: &Vec, e: f32) -> Vec {
+        let c = self.mulf(-1.0).dot(n).min(1.0);
+        let u = self.add(&[n.mulf(c)]).mulf(e);
+        let v = n.mulf(-(1.0 - u.len_sqrd()).abs().sqrt());
+        u.add(&[v])
     }
+}
 
-    pub fn randmm(min: f32, max: f32) -> Vec {
-        Vec {
-            x: util::util::randmm_f32(min, max),
-            y: util::util::randmm_f32(min, max),
-            z: util::util::randmm_f32(min, max),
-        }
+pub type Point = Vec;
+pub type Colour = Vec;
+
+pub fn rand(rng: &mut util::Rng) -> Vec {
+    Vec {
+        x: util::rand_f32(rng),
+        y: util::rand_f32(rng),
+        z: util::rand_f32(rng),
     }
+}
 
-    pub fn rand_unit_sphere() -> Vec {
-        loop {
-            let p = randmm(-1.0, 1.0);
-            if p.len_sqrd() < 1.0 {
-                return p;
-            }
-        }
+pub fn randmm(rng: &mut util::Rng, min: f32, max: f32) -> Vec {
+    Vec {
+        x: util::randmm_f32(rng, min, max),
+        y: util::randmm_f32(rng, min, max),
+        z: util::randmm_f32(rng, min, max),
     }
+}
 
-    pub fn rand_unit() -> Vec {
-        // rand_unit_sphere().unit()
-        randmm(-1.0, 1.0).unit()
+// Uniform direction on the unit sphere.
+pub fn rand_unit(sampler: &mut dyn sampler::Sampler) -> Vec {
+    let (u1, u2) = sampler.get_2d();
+    let z = 1.0 - 2.0 * u1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u2;
+    Vec {
+        x: r * phi.cos(),
+        y: r * phi.sin(),
+        z,
     }
+}
 
-    pub fn rand_unit_disk() -> Vec {
-        loop {
-            let p = Vec {
-                x: util::util::randmm_f32(-1.0, 1.0),
-                y: util::util::randmm_f32(-1.0, 1.0),
-                ..Default::default()
-            };
-            if p.len_sqrd() < 1.0 {
-                return p;
-            }
-        }
+// Uniform point inside the unit sphere.
+pub fn rand_unit_sphere(sampler: &mut dyn sampler::Sampler) -> Vec {
+    let dir = rand_unit(sampler);
+    dir.mulf(sampler.get_1d().cbrt())
+}
+
+// Uniform point inside the unit disk in the xy plane, using the concentric
+// mapping so that neighbouring samples stay neighbours on the disk.
+pub fn rand_unit_disk(sampler: &mut dyn sampler::Sampler) -> Vec {
+    let (u1, u2) = sampler.get_2d();
+    let a = 2.0 * u1 - 1.0;
+    let b = 2.0 * u2 - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return Default::default();
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, std::f32::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (
+            b,
+            std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4 * (a / b),
+        )
+    };
+    Vec {
+        x: r * theta.cos(),
+        y: r * theta.sin(),
+        ..Default::default()
     }
 }
 
@@ -147,18 +162,19 @@ pub mod vec {
 mod tests {
     use super::*;
     #[test]
+    #[allow(clippy::excessive_precision)]
     fn basic_arithmetic() {
-        let v = vec::Vec {
+        let v = Vec {
             x: 1.0,
             y: 2.0,
             z: 3.0,
         };
-        let u = vec::Vec {
+        let u = Vec {
             x: 4.0,
             y: 5.0,
             z: 6.0,
         };
-        let r = vec::Vec {
+        let r = Vec {
             x: 7.0,
             y: 8.0,
             z: 9.0,
@@ -167,7 +183,7 @@ mod tests {
         let mut t = v.add(&[u, r]);
         assert_eq!(
             t,
-            vec::Vec {
+            Vec {
                 x: 12.0,
                 y: 15.0,
                 z: 18.0,
@@ -177,7 +193,7 @@ mod tests {
         t = v.sub(&[u, r]);
         assert_eq!(
             t,
-            vec::Vec {
+            Vec {
                 x: -10.0,
                 y: -11.0,
                 z: -12.0,
@@ -187,7 +203,7 @@ mod tests {
         t = v.mul(&[u, r]);
         assert_eq!(
             t,
-            vec::Vec {
+            Vec {
                 x: 28.0,
                 y: 80.0,
                 z: 162.0,
@@ -199,7 +215,7 @@ mod tests {
 
         assert_eq!(
             v.mulf(2.0),
-            vec::Vec {
+            Vec {
                 x: 2.0,
                 y: 4.0,
                 z: 6.0,
@@ -208,7 +224,7 @@ mod tests {
 
         assert_eq!(
             v.divf(2.0),
-            vec::Vec {
+            Vec {
                 x: 0.5,
                 y: 1.0,
                 z: 1.5,
@@ -218,7 +234,7 @@ mod tests {
         assert_eq!(v.dot(&u), 32.0);
         assert_eq!(
             v.unit(),
-            vec::Vec {
+            Vec {
                 x: 0.26726124,
                 y: 0.5345225,
                 z: 0.8017837,
@@ -226,7 +242,7 @@ mod tests {
         );
         assert_eq!(
             v.cross(&u),
-            vec::Vec {
+            Vec {
                 x: -3.0,
                 y: 6.0,
                 z: -3.0,
@@ -0,0 +1,152 @@
+// Shapes and materials defined outside the crate, as a downstream user
+// would write them.
+
+use ray_tracer_rust::scene;
+use ray_tracer_rust::Aabb;
+use ray_tracer_rust::Colour;
+use ray_tracer_rust::HitRecord;
+use ray_tracer_rust::Hittable;
+use ray_tracer_rust::HittableList;
+use ray_tracer_rust::Hittables;
+use ray_tracer_rust::Lobe;
+use ray_tracer_rust::Material;
+use ray_tracer_rust::Materials;
+use ray_tracer_rust::Ray;
+use ray_tracer_rust::Renderer;
+use ray_tracer_rust::Sampler;
+use ray_tracer_rust::Settings;
+use ray_tracer_rust::Vec;
+
+// A square in the y = 0 plane, from -size to size in x and z.
+struct Square {
+    size: f32,
+    mat: Materials,
+}
+
+impl Hittable for Square {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let t = -r.orig.y / r.dir.y;
+        if !(t_min..=t_max).contains(&t) {
+            return false;
+        }
+        let p = r.at(t);
+        if p.x.abs() > self.size || p.z.abs() > self.size {
+            return false;
+        }
+        rec.t = t;
+        rec.p = p;
+        rec.set_face_norm(
+            r,
+            &Vec {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+        );
+        rec.mat = self.mat.clone();
+        rec.u = 0.5 + p.x / (2.0 * self.size);
+        rec.v = 0.5 + p.z / (2.0 * self.size);
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb {
+            min: Vec {
+                x: -self.size,
+                y: -1e-4,
+                z: -self.size,
+            },
+            max: Vec {
+                x: self.size,
+                y: 1e-4,
+                z: self.size,
+            },
+        }
+    }
+
+    fn material(&self) -> Materials {
+        self.mat.clone()
+    }
+}
+
+// Absorbs everything, though it looks green in albedo outputs.
+struct Black;
+
+impl Material for Black {
+    fn scatter(
+        &self,
+        _r: &Ray,
+        _rec: &HitRecord,
+        _att: &mut Colour,
+        _scattered: &mut Ray,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<Lobe> {
+        None
+    }
+
+    fn albedo(&self) -> Colour {
+        Colour {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        }
+    }
+}
+
+fn square() -> Hittables {
+    Hittables::custom(Square {
+        size: 2.0,
+        mat: Materials::custom(Black),
+    })
+}
+
+#[test]
+fn renders_custom_objects() {
+    let mut s = scene::parse("lookfrom 0 1 4").unwrap();
+    s.world.objects.push(square());
+    let image = Renderer::new(&s)
+        .settings(Settings {
+            image_width: 16,
+            aspect_ratio: 2.0,
+            n_samples: 4,
+            ..Default::default()
+        })
+        .render()
+        .unwrap();
+    // The square fills the bottom of the image and absorbs everything,
+    // leaving sky above it.
+    let bottom = image.pixel(8, 7);
+    assert_eq!((bottom.x, bottom.y, bottom.z), (0.0, 0.0, 0.0));
+    assert!(image.pixel(8, 0).z > 0.5);
+}
+
+#[test]
+fn custom_objects_move_and_keep_their_material() {
+    let mut obj = square();
+    obj.translate(&Vec {
+        x: 0.0,
+        y: -10.0,
+        z: 0.0,
+    });
+    let mut world: HittableList = Default::default();
+    world.objects.push(obj);
+    world.build_bvh();
+
+    let down = Ray {
+        orig: Default::default(),
+        dir: Vec {
+            x: 0.1,
+            y: -1.0,
+            z: 0.0,
+        },
+    };
+    let mut rec: HitRecord = Default::default();
+    assert!(world.hit(&down, 0.001, f32::MAX, &mut rec));
+    assert_eq!((rec.t, rec.p.x, rec.p.y), (10.0, 1.0, -10.0));
+    assert!(!world.hit(&down, 0.001, 9.0, &mut rec));
+
+    let mat = world.objects[0].material();
+    assert_eq!(mat.albedo().y, 1.0);
+    assert!(mat == rec.mat);
+    assert!(mat != Materials::custom(Black));
+}
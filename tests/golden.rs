@@ -0,0 +1,125 @@
+// Golden image tests: small reference scenes rendered at a fixed seed and
+// compared against the images stored in tests/golden. Run with
+// UPDATE_GOLDEN=1 to (re)write the references after an intended change. On
+// a mismatch the render and a difference image are left next to the build
+// output, and the failure message says where.
+
+use ray_tracer_rust::render_scene;
+use ray_tracer_rust::scene;
+use ray_tracer_rust::settings;
+use ray_tracer_rust::Framebuffer;
+
+const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+
+// Root mean square error, over all channels in [0, 1], that still passes.
+// Renders use a fixed seed and are deterministic, so the error is zero where
+// the references were written; this absorbs the odd 8 bit level flipped by
+// floating point differences between platforms. It is well below the noise
+// between two seeds at these sample counts (RMSE 0.017 to 0.025), so any
+// change to sampling or shading fails, biased or not, and needs
+// UPDATE_GOLDEN. Dropping the Russian roulette weight costs 0.027 on
+// russian_roulette and 0.004 to 0.011 elsewhere.
+const TOLERANCE: f32 = 0.004;
+
+// Renders tests/golden/<name>.scene with the given command line options.
+fn render(name: &str, args: &str) -> Framebuffer {
+    let settings = settings::parse(args.split_whitespace().map(String::from)).unwrap();
+    let scene = scene::load(&format!("{GOLDEN}/{name}.scene")).unwrap();
+    render_scene(&scene, &settings, None).unwrap()
+}
+
+// Reads a plain (P3) PPM as its width, height and 8 bit channel values.
+fn read_ppm(path: &str) -> (usize, usize, Vec<u8>) {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| panic!("{path}: {e}"));
+    let mut words = text.split_whitespace();
+    assert_eq!(words.next(), Some("P3"), "{path} is not a plain PPM");
+    let mut next = || words.next().unwrap().parse::<usize>().unwrap();
+    let (width, height, _) = (next(), next(), next());
+    let data = (0..width * height * 3).map(|_| next() as u8).collect();
+    (width, height, data)
+}
+
+fn write_ppm(path: &str, width: usize, height: usize, data: &[u8]) {
+    let mut text = format!("P3\n{width} {height}\n255\n");
+    for px in data.chunks_exact(3) {
+        text += &format!("{} {} {}\n", px[0], px[1], px[2]);
+    }
+    std::fs::write(path, text).unwrap();
+}
+
+fn check(name: &str, args: &str) {
+    let out_dir = concat!(env!("CARGO_TARGET_TMPDIR"), "/golden");
+    std::fs::create_dir_all(out_dir).unwrap();
+    let actual_path = format!("{out_dir}/{name}.ppm");
+    render(name, args).write_ppm(&actual_path).unwrap();
+
+    let golden_path = format!("{GOLDEN}/{name}.ppm");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::copy(&actual_path, &golden_path).unwrap();
+        return;
+    }
+    let (w, h, expected) = read_ppm(&golden_path);
+    let (aw, ah, actual) = read_ppm(&actual_path);
+    assert_eq!((aw, ah), (w, h), "{name}: size differs from {golden_path}");
+
+    let sq: f32 = expected
+        .iter()
+        .zip(&actual)
+        .map(|(&a, &b)| ((a as f32 - b as f32) / 255.0).powi(2))
+        .sum();
+    let rmse = (sq / expected.len() as f32).sqrt();
+    if rmse > TOLERANCE {
+        // Differences amplified four times, so small ones are visible.
+        let diff: Vec<u8> = expected
+            .iter()
+            .zip(&actual)
+            .map(|(&a, &b)| (a.abs_diff(b) as u32 * 4).min(255) as u8)
+            .collect();
+        let diff_path = format!("{out_dir}/{name}.diff.ppm");
+        write_ppm(&diff_path, w, h, &diff);
+        panic!(
+            "{name}: RMSE {rmse:.4} exceeds {TOLERANCE} against {golden_path}\n\
+             rendered: {actual_path}\ndifference: {diff_path}"
+        );
+    }
+}
+
+#[test]
+fn materials() {
+    check(
+        "materials",
+        "--width 64 --aspect 2 --samples 16 --max-depth 8",
+    );
+}
+
+#[test]
+fn depth_of_field() {
+    check(
+        "depth_of_field",
+        "--width 64 --aspect 2 --samples 32 --max-depth 8 --sampler stratified",
+    );
+}
+
+#[test]
+fn orthographic() {
+    check(
+        "orthographic",
+        "--width 48 --aspect 1 --samples 16 --max-depth 8 --filter mitchell",
+    );
+}
+
+#[test]
+fn fisheye() {
+    check(
+        "fisheye",
+        "--width 48 --aspect 1 --samples 16 --max-depth 8 --sampler halton --seed 3",
+    );
+}
+
+#[test]
+fn russian_roulette() {
+    check(
+        "russian_roulette",
+        "--width 64 --aspect 2 --samples 16 --max-depth 16 --rr-depth 1",
+    );
+}
@@ -0,0 +1,365 @@
+// Statistical checks of the materials' scattering: white furnace and energy
+// conservation, reciprocity, and chi-square tests of the sampled directions
+// against the distributions each material is meant to have. Every test uses a
+// fixed seed, so they are deterministic; the thresholds are set where a
+// correct material fails with probability around 1 in 1000.
+
+use ray_tracer_rust::dielectric::Dielectric;
+use ray_tracer_rust::hittable::HitRecord;
+use ray_tracer_rust::lambertian::Lambertian;
+use ray_tracer_rust::material::Lobe;
+use ray_tracer_rust::material::Material;
+use ray_tracer_rust::material::Materials;
+use ray_tracer_rust::metal::Metal;
+use ray_tracer_rust::ray::Ray;
+use ray_tracer_rust::sampler::Sampler;
+use ray_tracer_rust::sampler::Samplers;
+use ray_tracer_rust::vec::Colour;
+use ray_tracer_rust::vec::Vec;
+
+use std::f64::consts::PI;
+
+fn v(x: f32, y: f32, z: f32) -> Vec {
+    Vec { x, y, z }
+}
+
+fn white() -> Colour {
+    v(1.0, 1.0, 1.0)
+}
+
+fn sampler(seed: u64) -> Samplers {
+    Samplers::new("independent", seed, 1).unwrap()
+}
+
+// Direction travelling down onto the xy plane at theta degrees from the
+// normal, or up from underneath it with a negative theta.
+fn incident(theta: f32) -> Vec {
+    let (s, c) = theta.to_radians().sin_cos();
+    if theta >= 0.0 {
+        v(s, 0.0, -c)
+    } else {
+        v(-s, 0.0, c)
+    }
+}
+
+// Scatters a ray travelling along dir off the xy plane, whose outward normal
+// is +z, returning the direction and attenuation if it isn't absorbed.
+fn scatter(mat: &Materials, dir: Vec, sampler: &mut dyn Sampler) -> Option<(Vec, Colour, Lobe)> {
+    let r = Ray {
+        orig: v(0.0, 0.0, 0.0),
+        dir,
+    };
+    let mut rec = HitRecord {
+        t: 1.0,
+        ..Default::default()
+    };
+    rec.set_face_norm(&r, &v(0.0, 0.0, 1.0));
+    let mut att = Default::default();
+    let mut scattered = Default::default();
+    let lobe = mat.scatter(&r, &rec, &mut att, &mut scattered, sampler)?;
+    Some((scattered.dir.unit(), att, lobe))
+}
+
+// Fails if the chi-square statistic of the observed counts against the
+// expected ones is beyond the 0.999 quantile for their degrees of freedom
+// (Wilson-Hilferty approximation).
+fn chi_square(name: &str, observed: &[u64], expected: &[f64]) {
+    assert!(expected.iter().all(|&e| e >= 5.0), "{name}: bins too small");
+    let stat: f64 = observed
+        .iter()
+        .zip(expected)
+        .map(|(&o, &e)| (o as f64 - e).powi(2) / e)
+        .sum();
+    let k = (observed.len() - 1) as f64;
+    let h = 2.0 / (9.0 * k);
+    let critical = k * (1.0 - h + 3.09 * h.sqrt()).powi(3);
+    assert!(
+        stat < critical,
+        "{name}: chi-square {stat:.1} over {critical:.1}"
+    );
+}
+
+// Schlick's approximation of the Fresnel reflectance, with the cosine on the
+// side of the interface with the lower index.
+fn schlick(cos: f64, ior: f64) -> f64 {
+    let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+}
+
+#[test]
+fn white_furnace() {
+    // A white material that never absorbs returns every ray at full
+    // strength, so its average throughput is exactly 1.
+    let materials = [
+        Materials::Lambertian(Lambertian { albedo: white() }),
+        Materials::Metal(Metal {
+            albedo: white(),
+            fuzz: 0.0,
+        }),
+        Materials::Dielectric(Dielectric { ir: 1.5 }),
+    ];
+    let mut s = sampler(1);
+    for mat in &materials {
+        for theta in [0.0, 30.0, 60.0, 89.0, -20.0, -70.0] {
+            let n = 1000;
+            let total: f32 = (0..n)
+                .filter_map(|_| scatter(mat, incident(theta), &mut s))
+                .map(|(_, att, _)| att.x)
+                .sum();
+            assert_eq!(total, n as f32, "at {theta} degrees");
+        }
+    }
+
+    // A fuzzy white metal loses the rays it scatters into the surface, more
+    // of them towards grazing angles, but never gains energy.
+    let fuzzy = Materials::Metal(Metal {
+        albedo: white(),
+        fuzz: 0.5,
+    });
+    let mut last = 1.0;
+    for theta in [0.0, 45.0, 70.0, 85.0] {
+        let n = 20000;
+        let kept = (0..n)
+            .filter(|_| scatter(&fuzzy, incident(theta), &mut s).is_some())
+            .count() as f32
+            / n as f32;
+        assert!(kept <= last, "{theta} degrees: {kept} > {last}");
+        last = kept;
+    }
+    assert!(last < 0.9, "{last}");
+}
+
+#[test]
+fn energy_conservation() {
+    let mut rng = sampler(2);
+    let mut s = sampler(3);
+    for _ in 0..200 {
+        let albedo = v(rng.get_1d(), rng.get_1d(), rng.get_1d());
+        let materials = [
+            Materials::Lambertian(Lambertian { albedo }),
+            Materials::Metal(Metal {
+                albedo,
+                fuzz: rng.get_1d(),
+            }),
+            Materials::Dielectric(Dielectric {
+                ir: 1.0 + rng.get_1d(),
+            }),
+        ];
+        let theta = 179.0 * rng.get_1d() - 89.5;
+        let dir = incident(theta);
+        for mat in &materials {
+            let n = 200;
+            let mut total = Colour::default();
+            for _ in 0..n {
+                let Some((out, att, lobe)) = scatter(mat, dir, &mut s) else {
+                    continue;
+                };
+                total = total.add(&[att]);
+                assert!((out.len() - 1.0).abs() < 1e-4);
+                // Reflections leave on the side the ray came from and
+                // transmissions on the other.
+                let same_side = out.z * dir.z < 0.0;
+                assert_eq!(same_side, lobe != Lobe::Transmission, "{lobe:?} {out:?}");
+            }
+            let mean = total.divf(n as f32);
+            // Up to rounding in the sum.
+            let albedo = mat.albedo().mulf(1.0 + 1e-4);
+            assert!(
+                mean.x <= albedo.x && mean.y <= albedo.y && mean.z <= albedo.z,
+                "{mean:?} from albedo {albedo:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn reciprocity() {
+    // The fraction of rays arriving along -a that leave within a small cone
+    // around b. For a reciprocal BSDF, divided by the cosine at b it matches
+    // the fraction arriving along -b that leave around a, divided by the
+    // cosine at a.
+    let cone = 3.0f32.to_radians().cos();
+    let fraction = |mat: &Materials, a: Vec, b: Vec, seed: u64| {
+        let mut s = sampler(seed);
+        let n = 400_000;
+        let hits = (0..n)
+            .filter_map(|_| scatter(mat, a.mulf(-1.0), &mut s))
+            .filter(|(out, _, _)| out.dot(&b) > cone)
+            .count();
+        hits as f64 / n as f64
+    };
+    let dir = |theta: f32, phi: f32| {
+        let (st, ct) = theta.to_radians().sin_cos();
+        let (sp, cp) = phi.to_radians().sin_cos();
+        v(st * cp, st * sp, ct)
+    };
+
+    // Lambertian: f is constant, so p(wo) / cos(wo) is the same both ways.
+    let lambertian = Materials::Lambertian(Lambertian { albedo: white() });
+    let (a, b) = (dir(20.0, 0.0), dir(60.0, 120.0));
+    let ab = fraction(&lambertian, a, b, 4) / b.z as f64;
+    let ba = fraction(&lambertian, b, a, 5) / a.z as f64;
+    assert!((ab - ba).abs() < 0.1 * ab, "{ab} {ba}");
+
+    // Metal: the fuzzy lobe around the mirror direction is symmetric, so
+    // swapping the directions gives the same density.
+    let metal = Materials::Metal(Metal {
+        albedo: white(),
+        fuzz: 0.3,
+    });
+    let (a, b) = (dir(30.0, 180.0), dir(40.0, 10.0));
+    let ab = fraction(&metal, a, b, 6);
+    let ba = fraction(&metal, b, a, 7);
+    assert!(ab > 0.0 && (ab - ba).abs() < 0.1 * ab, "{ab} {ba}");
+
+    // Dielectric: light crossing the surface either way along the same line
+    // is reflected with the same probability.
+    let glass = Materials::Dielectric(Dielectric { ir: 1.5 });
+    for theta in [10.0f32, 45.0, 75.0] {
+        let inside = (theta.to_radians().sin() / 1.5).asin().to_degrees();
+        let mut s = sampler(8);
+        let n = 100_000;
+        let mut reflected = |dir: Vec| {
+            (0..n)
+                .filter(|_| scatter(&glass, dir, &mut s).unwrap().2 == Lobe::Specular)
+                .count() as f64
+                / n as f64
+        };
+        let entering = reflected(incident(theta));
+        let leaving = reflected(incident(-inside));
+        assert!(
+            (entering - leaving).abs() < 0.01,
+            "{theta} degrees: {entering} entering, {leaving} leaving"
+        );
+    }
+}
+
+#[test]
+fn lambertian_chi_square() {
+    // Cosine weighted directions have cos^2(theta) and phi uniformly
+    // distributed, so equal bins of both expect equal counts.
+    let mat = Materials::Lambertian(Lambertian { albedo: white() });
+    let (nu, nphi, n) = (10, 10, 100_000);
+    let mut s = sampler(9);
+    let mut counts = vec![0; nu * nphi];
+    for k in 0..n {
+        let theta = 80.0 * (k % 9) as f32 / 8.0;
+        let (out, _, _) = scatter(&mat, incident(theta), &mut s).unwrap();
+        let u = ((out.z * out.z) as f64 * nu as f64) as usize;
+        let phi = (out.y as f64).atan2(out.x as f64) + PI;
+        let p = (phi / (2.0 * PI) * nphi as f64) as usize;
+        counts[u.min(nu - 1) * nphi + p.min(nphi - 1)] += 1;
+    }
+    chi_square(
+        "lambertian",
+        &counts,
+        &vec![n as f64 / (nu * nphi) as f64; nu * nphi],
+    );
+}
+
+#[test]
+fn metal_chi_square() {
+    // At normal incidence the reflection is +z, and a fuzzy metal moves it to
+    // a uniform point in a ball of radius fuzz around it. The share of
+    // directions at angle beta from +z is proportional to the length of ray
+    // within the ball, weighted by the distance squared:
+    // ((c + s)^3 - (c - s)^3) / 3 with c = cos(beta) and
+    // s = sqrt(fuzz^2 - sin^2(beta)).
+    let fuzz = 0.5f64;
+    let mat = Materials::Metal(Metal {
+        albedo: white(),
+        fuzz: fuzz as f32,
+    });
+    let max = fuzz.asin();
+    let density = |beta: f64| {
+        let (sb, c) = beta.sin_cos();
+        let s = (fuzz * fuzz - sb * sb).max(0.0).sqrt();
+        ((c + s).powi(3) - (c - s).powi(3)) / 3.0 * sb * 2.0 * PI
+    };
+    let volume = 4.0 / 3.0 * PI * fuzz.powi(3);
+
+    let (nbeta, nphi, n) = (8, 8, 200_000);
+    // Bins of equal width in beta, the last running to the edge of the lobe.
+    let mut expected = vec![];
+    for i in 0..nbeta {
+        let (b0, b1) = (
+            max * i as f64 / nbeta as f64,
+            max * (i + 1) as f64 / nbeta as f64,
+        );
+        let steps = 1000;
+        let h = (b1 - b0) / steps as f64;
+        // Simpson's rule.
+        let integral: f64 = (0..=steps)
+            .map(|k| {
+                let w = if k == 0 || k == steps {
+                    1.0
+                } else if k % 2 == 1 {
+                    4.0
+                } else {
+                    2.0
+                };
+                w * density(b0 + k as f64 * h)
+            })
+            .sum::<f64>()
+            * h
+            / 3.0;
+        let p = integral / volume / nphi as f64;
+        expected.extend(std::iter::repeat_n(p * n as f64, nphi));
+    }
+    let total: f64 = expected.iter().sum();
+    assert!((total - n as f64).abs() < 1e-3 * n as f64, "{total}");
+
+    let mut s = sampler(10);
+    let mut counts = vec![0; nbeta * nphi];
+    for _ in 0..n {
+        let (out, _, _) = scatter(&mat, incident(0.0), &mut s).unwrap();
+        let beta = (out.z as f64).clamp(-1.0, 1.0).acos();
+        let b = (beta / max * nbeta as f64) as usize;
+        let phi = (out.y as f64).atan2(out.x as f64) + PI;
+        let p = (phi / (2.0 * PI) * nphi as f64) as usize;
+        counts[b.min(nbeta - 1) * nphi + p.min(nphi - 1)] += 1;
+    }
+    chi_square("metal", &counts, &expected);
+}
+
+#[test]
+fn dielectric_chi_square() {
+    // Each ray is reflected with the Schlick reflectance, or always past the
+    // critical angle, and refracted otherwise.
+    let ior = 1.5f64;
+    let mat = Materials::Dielectric(Dielectric { ir: ior as f32 });
+    let n = 20_000;
+    let mut s = sampler(11);
+    let mut observed = vec![];
+    let mut expected = vec![];
+    for theta in [0.0f64, 30.0, 60.0, 80.0, 88.0, -20.0, -40.0, -41.0, -45.0] {
+        let (sin, cos) = theta.abs().to_radians().sin_cos();
+        let r = if theta >= 0.0 {
+            schlick(cos, ior)
+        } else if sin * ior > 1.0 {
+            1.0
+        } else {
+            // Leaving the glass, the cosine is taken outside it.
+            schlick((1.0 - (sin * ior).powi(2)).sqrt(), ior)
+        };
+        let reflected = (0..n)
+            .filter(|_| {
+                let (out, _, lobe) = scatter(&mat, incident(theta as f32), &mut s).unwrap();
+                if lobe == Lobe::Transmission {
+                    // Snell's law.
+                    let (ni, nt) = if theta >= 0.0 { (1.0, ior) } else { (ior, 1.0) };
+                    let sin_t = ((out.x * out.x + out.y * out.y) as f64).sqrt();
+                    assert!((ni * sin - nt * sin_t).abs() < 1e-4, "{theta}: {out:?}");
+                }
+                lobe == Lobe::Specular
+            })
+            .count() as u64;
+        if r < 1.0 {
+            observed.extend([reflected, n - reflected]);
+            expected.extend([r * n as f64, (1.0 - r) * n as f64]);
+        } else {
+            assert_eq!(reflected, n, "{theta} degrees is past the critical angle");
+        }
+    }
+    chi_square("dielectric", &observed, &expected);
+}